@@ -1,10 +1,12 @@
-use crate::{BlobMsg, HexU32, MsgTable, UbusBlob, UbusError, UbusMsgStatus, valid_data};
+use crate::{
+    BlobMsg, HexU32, MsgTable, ParseOptions, UbusBlob, UbusError, UbusMsgStatus, valid_data,
+};
 
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::convert::{TryFrom, TryInto};
 use core::mem::{align_of, size_of, transmute};
-use std::borrow::ToOwned;
-use std::string::String;
-use std::vec::Vec;
 use storage_endian::BEu32;
 
 /**
@@ -36,44 +38,81 @@ pub enum Blob {
  *
  * The actual convertion happens in BlobMsg::try_from and UbusBlob::from_bytes
  *
+ * A malformed blob is yielded as `Err` (located by offset), then the iteration stops.
+ * With `ParseOptions::lenient`, it's logged and skipped instead, like the ubus cli does
  */
 pub struct BlobIter<'a> {
     data: &'a [u8],
-    // _phantom: PhantomData<T>,
+    /* where `data` starts in the bytes given to `new()`, to locate errors */
+    offset: usize,
+    options: ParseOptions,
 }
 impl<'a> BlobIter<'a> {
     pub fn new(data: &'a [u8]) -> Self {
+        Self::with_options(data, ParseOptions::default())
+    }
+
+    pub fn with_options(data: &'a [u8], options: ParseOptions) -> Self {
         Self {
             data,
-            // _phantom: PhantomData,
+            offset: 0,
+            options,
         }
     }
+
+    /**
+     * bytes consumed so far
+     */
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /* the last blob may come without padding */
+    fn advance(&mut self, tag: BlobTag) {
+        let next_idx = tag.next_tag().min(self.data.len());
+        self.data = &self.data[next_idx..];
+        self.offset += next_idx;
+    }
 }
 impl<'a> Iterator for BlobIter<'a> {
-    type Item = Blob;
+    type Item = Result<Blob, UbusError>;
     fn next(&mut self) -> Option<Self::Item> {
         // dbg!(&self.data);
-        if self.data.len() < BlobTag::SIZE {
-            return None;
-        }
-
-        let tag = BlobTag::from_bytes(&self.data[..BlobTag::SIZE].try_into().unwrap());
-        if tag.is_extended() {
-            if let Ok(blob) = BlobMsg::try_from(&self.data[..]) {
-                // Advance the internal pointer to the next tag
-                let next_idx = tag.next_tag();
-                self.data = &self.data[next_idx..];
-                return Some(Blob::BlogMsg(blob));
-            }
-        } else {
-            if let Ok(blob) = UbusBlob::try_from(&self.data[..]) {
-                // Advance the internal pointer to the next tag
-                let next_idx = tag.next_tag();
-                self.data = &self.data[next_idx..];
-                return Some(Blob::UbusBlob(blob));
+        while self.data.len() >= BlobTag::SIZE {
+            let tag = BlobTag::from_bytes(&self.data[..BlobTag::SIZE].try_into().unwrap());
+            let blob = if tag.is_extended() {
+                BlobMsg::from_bytes_with_options(self.data, self.options).map(Blob::BlogMsg)
+            } else {
+                UbusBlob::from_bytes_with_options(self.data, self.options).map(Blob::UbusBlob)
+            };
+            let offset = self.offset;
+
+            match blob {
+                Ok(blob) => {
+                    // Advance the internal pointer to the next tag
+                    self.advance(tag);
+                    return Some(Ok(blob));
+                }
+                /* the length is sane, so the following blobs can still be found */
+                Err(e)
+                    if self.options.lenient
+                        && tag.is_valid().is_ok()
+                        && tag.size() <= self.data.len() =>
+                {
+                    log::warn!("skip malformed blob: {}", e.at(offset, tag.blob_type()));
+                    self.advance(tag);
+                }
+                Err(e) => {
+                    let e = e.at(offset, tag.blob_type());
+                    if self.options.lenient {
+                        log::warn!("drop malformed blobs since: {}", e);
+                    }
+                    self.offset += self.data.len();
+                    self.data = &[];
+                    return (!self.options.lenient).then_some(Err(e));
+                }
             }
         }
-
         None
     }
 }
@@ -118,6 +157,10 @@ impl BlobTag {
         }
     }
 
+    /// Create BlobTag from the start of bytes, `None` if too short
+    pub fn from_slice(data: &[u8]) -> Option<Self> {
+        Some(Self::from_bytes(data.get(..Self::SIZE)?.try_into().ok()?))
+    }
     /// Create BlobTag from a byte array
     pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
         unsafe { transmute(bytes.to_owned()) }
@@ -179,10 +222,13 @@ impl core::fmt::Debug for BlobTag {
 
 /**
  * `BlobBuilder` is used to encode `Blob` from "native rust struct" to "raw bytes on wire"
+ *
+ * everything is written into one buffer in a single pass, nested blobs work like `blob_nest_start()`/`blob_nest_end()`
+ * in libubox: `open_nest()` writes a placeholder tag, and `close()` patches its length in place.
+ * The buffer can be reused with `clear()` to encode message after message without allocating
  */
-pub struct BlobBuilder {
-    buffer: Vec<u8>,
-    offset: usize,
+pub struct BlobBuilder<B: BlobBuffer = Vec<u8>> {
+    buffer: B,
 }
 impl Into<Vec<u8>> for BlobBuilder {
     fn into(self) -> Vec<u8> {
@@ -190,12 +236,76 @@ impl Into<Vec<u8>> for BlobBuilder {
     }
 }
 
+/**
+ * what `BlobBuilder` can write into, `Vec<u8>`, and `bytes::BytesMut` with feature `bytes`
+ */
+pub trait BlobBuffer: Default {
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn clear(&mut self);
+    fn extend_from_slice(&mut self, data: &[u8]);
+    fn as_slice(&self) -> &[u8];
+    fn as_mut_slice(&mut self) -> &mut [u8];
+}
+
+impl BlobBuffer for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        Vec::extend_from_slice(self, data)
+    }
+    fn as_slice(&self) -> &[u8] {
+        Vec::as_slice(self)
+    }
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        Vec::as_mut_slice(self)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl BlobBuffer for bytes::BytesMut {
+    fn len(&self) -> usize {
+        bytes::BytesMut::len(self)
+    }
+    fn clear(&mut self) {
+        bytes::BytesMut::clear(self)
+    }
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        bytes::BytesMut::extend_from_slice(self, data)
+    }
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+/**
+ * returned by `BlobBuilder::open_nest()` and friends, must be given back to `BlobBuilder::close()`
+ */
+#[must_use = "a nested blob must be closed, or its length stays wrong"]
+#[derive(Debug)]
+pub struct BlobNest {
+    offset: usize,
+}
+
 impl BlobBuilder {
     pub fn new() -> Self {
-        Self {
-            buffer: Vec::new(),
-            offset: 0,
-        }
+        Self { buffer: Vec::new() }
+    }
+
+    /**
+     * append blobs to the end of `buffer`, e.g. after an already written `UbusMsgHeader`
+     */
+    pub fn from_vec(buffer: Vec<u8>) -> Self {
+        Self { buffer }
     }
 
     pub fn to_bytes(self) -> Vec<u8> {
@@ -205,6 +315,30 @@ impl BlobBuilder {
     pub fn to_bytes_clone(&self) -> Vec<u8> {
         self.buffer.to_owned()
     }
+}
+
+impl<B: BlobBuffer> BlobBuilder<B> {
+    /**
+     * append blobs to the end of any `BlobBuffer`
+     */
+    pub fn with_buffer(buffer: B) -> Self {
+        Self { buffer }
+    }
+
+    pub fn into_inner(self) -> B {
+        self.buffer
+    }
+
+    /**
+     * drop everything written but keep the allocation
+     */
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.buffer.as_slice()
+    }
 
     pub fn push_u32(&mut self, id: u32, data: u32) -> Result<(), UbusError> {
         self.push_bytes(id, &data.to_be_bytes())
@@ -215,43 +349,59 @@ impl BlobBuilder {
     }
 
     pub fn push_str(&mut self, id: u32, data: &str) -> Result<(), UbusError> {
-        self.push_bytes(id, data.as_bytes().iter().chain([0u8].iter()))
-    }
-
-    pub fn push_bytes<'b>(
-        &mut self,
-        id: u32,
-        data: impl IntoIterator<Item = &'b u8>,
-    ) -> Result<(), UbusError> {
-        // Collect data into a Vec<u8> first (allocates)
-        let bytes: Vec<u8> = data.into_iter().copied().collect();
-        let data_len = bytes.len();
-        let tag_len = BlobTag::SIZE;
-
-        // Build the tag to compute padding
-        let tag = BlobTag::try_build(id, tag_len + data_len, false)?;
-        let pad_len = tag.padding();
-        let total_len = tag_len + data_len + pad_len;
-
-        // Ensure the buffer is large enough
-        if self.offset + total_len > self.buffer.len() {
-            self.buffer.resize(self.offset + total_len, 0);
-        }
+        let nest = self.open_nest(id)?;
+        self.buffer.extend_from_slice(data.as_bytes());
+        self.buffer.extend_from_slice(&[0u8]);
+        self.close(nest)
+    }
 
-        // Write tag header
-        self.buffer[self.offset..self.offset + tag_len].copy_from_slice(&tag.to_bytes());
+    pub fn push_bytes(&mut self, id: u32, data: &[u8]) -> Result<(), UbusError> {
+        let nest = self.open_nest(id)?;
+        self.buffer.extend_from_slice(data);
+        self.close(nest)
+    }
 
-        // Write data
-        self.buffer[self.offset + tag_len..self.offset + tag_len + data_len]
-            .copy_from_slice(&bytes);
+    /**
+     * start a blob of `id` whose payload is whatever pushed until `close()`
+     */
+    pub fn open_nest(&mut self, id: u32) -> Result<BlobNest, UbusError> {
+        self.open_tag(id, false)
+    }
 
+    /**
+     * set the length of the blob started by `nest`, and pad it to ALIGNMENT
+     */
+    pub fn close(&mut self, nest: BlobNest) -> Result<(), UbusError> {
+        let tag_range = nest.offset..nest.offset + BlobTag::SIZE;
+        let tag = BlobTag::from_bytes(
+            self.buffer.as_slice()[tag_range.clone()]
+                .try_into()
+                .unwrap(),
+        );
+        let tag = BlobTag::try_build(
+            tag.blob_type(),
+            self.buffer.len() - nest.offset,
+            tag.is_extended(),
+        )?;
+        self.buffer.as_mut_slice()[tag_range].copy_from_slice(&tag.to_bytes());
         // Zero padding
-        self.buffer[self.offset + tag_len + data_len..self.offset + total_len].fill(0);
+        self.buffer
+            .extend_from_slice(&[0u8; BlobTag::ALIGNMENT][..tag.padding()]);
+        Ok(())
+    }
 
-        // Advance offset
-        self.offset += total_len;
+    /* write a placeholder tag, the length is patched in `close()` */
+    pub(crate) fn open_tag(&mut self, id: u32, extended: bool) -> Result<BlobNest, UbusError> {
+        let nest = BlobNest {
+            offset: self.buffer.len(),
+        };
+        let tag = BlobTag::try_build(id, BlobTag::SIZE, extended)?;
+        self.buffer.extend_from_slice(&tag.to_bytes());
+        Ok(nest)
+    }
 
-        Ok(())
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
     }
 
     pub fn is_empty(&self) -> bool {
@@ -259,7 +409,7 @@ impl BlobBuilder {
     }
 
     pub fn len(&self) -> usize {
-        self.offset
+        self.buffer.len()
     }
 }
 impl BlobBuilder {
@@ -281,10 +431,7 @@ impl BlobBuilder {
         Ok(builder)
     }
 
-    pub fn from_bytes<'b>(
-        id: u32,
-        data: impl IntoIterator<Item = &'b u8>,
-    ) -> Result<Self, UbusError> {
+    pub fn from_bytes(id: u32, data: &[u8]) -> Result<Self, UbusError> {
         let mut builder = Self::new();
         builder.push_bytes(id, data)?;
         Ok(builder)
@@ -316,10 +463,9 @@ macro_rules! payload_try_into_number {
             type Error = UbusError;
             fn try_from(val: BlobPayloadParser<'a>) -> Result<Self, Self::Error> {
                 let size = size_of::<Self>();
-                if let Ok(bytes) = val.0[..size].try_into() {
-                    Ok(<Self>::from_be_bytes(bytes))
-                } else {
-                    Err(UbusError::InvalidData(stringify!("Blob wrong size for " $ty)))
+                match val.0.get(..size).map(TryInto::try_into) {
+                    Some(Ok(bytes)) => Ok(<Self>::from_be_bytes(bytes)),
+                    _ => Err(UbusError::InvalidData(concat!("Blob too short for ", stringify!($ty)))),
                 }
             }
         }
@@ -337,8 +483,10 @@ impl<'a> TryFrom<BlobPayloadParser<'a>> for HexU32 {
 impl<'a> TryFrom<BlobPayloadParser<'a>> for bool {
     type Error = UbusError;
     fn try_from(parser: BlobPayloadParser<'a>) -> Result<Self, Self::Error> {
-        let value: u8 = parser.0[0];
-        Ok(value != 0)
+        match parser.0.first() {
+            Some(value) => Ok(*value != 0),
+            None => Err(UbusError::InvalidData("Blob too short for bool")),
+        }
     }
 }
 
@@ -367,9 +515,9 @@ impl<'a> TryFrom<BlobPayloadParser<'a>> for MsgTable {
 impl<'a> TryFrom<BlobPayloadParser<'a>> for Vec<BlobMsg> {
     type Error = UbusError;
     fn try_from(parser: BlobPayloadParser<'a>) -> Result<Self, Self::Error> {
-        Ok(BlobIter::new(parser.into())
-            .map(|blob| blob.try_into())
-            .try_collect::<Vec<BlobMsg>>()?)
+        BlobIter::new(parser.into())
+            .map(|blob| blob?.try_into())
+            .try_collect::<Vec<BlobMsg>>()
     }
 }
 
@@ -388,11 +536,6 @@ impl<'a> From<BlobPayloadParser<'a>> for &'a [u8] {
 impl<'a> TryFrom<BlobPayloadParser<'a>> for UbusMsgStatus {
     type Error = UbusError;
     fn try_from(parser: BlobPayloadParser<'a>) -> Result<Self, Self::Error> {
-        let size = size_of::<i32>();
-        if let Ok(bytes) = parser.0[..size].try_into() {
-            Ok(UbusMsgStatus(u32::from_be_bytes(bytes)))
-        } else {
-            Err(UbusError::InvalidData(stringify!("Blob wrong size for")))
-        }
+        Ok(UbusMsgStatus(u32::try_from(parser)?))
     }
 }
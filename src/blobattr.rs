@@ -0,0 +1,294 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BlobBuffer, BlobBuilder, BlobTag, ParseOptions, UbusError, Utf8Policy, valid_data, values,
+};
+
+/* `enum blob_attr_type` in blob.h */
+values!(pub BlobAttrType(u32) {
+    UNSPEC = 0,
+    NESTED = 1,
+    BINARY = 2,
+    STRING = 3,
+    INT8   = 4,
+    INT16  = 5,
+    INT32  = 6,
+    INT64  = 7,
+    DOUBLE = 8,
+});
+
+/**
+ * `BlobAttrInfo` is `struct blob_attr_info` in libubox, what the attribute of an id should look like
+ *
+ * a table of them is indexed by the attribute id, same as the `info[]` given to `blob_parse()`.
+ * Ids beyond the table, and ids of `UNSPEC`, are kept as raw `BlobAttrPayload::Binary`
+ *
+ * ```
+ * use ubus::{BlobAttr, BlobAttrInfo, BlobAttrPayload, BlobAttrType, BlobBuilder};
+ * static ADDR: [BlobAttrInfo; 2] = [
+ *     BlobAttrInfo::new(BlobAttrType::STRING),
+ *     BlobAttrInfo::new(BlobAttrType::INT8).max_len(1),
+ * ];
+ * static IFACE: [BlobAttrInfo; 2] = [
+ *     BlobAttrInfo::new(BlobAttrType::STRING).min_len(2),
+ *     BlobAttrInfo::nested(&ADDR),
+ * ];
+ * let attrs = [
+ *     BlobAttr::new(0, BlobAttrPayload::String("eth0".into())),
+ *     BlobAttr::new(1, BlobAttrPayload::Nested(vec![
+ *         BlobAttr::new(0, BlobAttrPayload::String("192.168.1.1".into())),
+ *         BlobAttr::new(1, BlobAttrPayload::Int8(24)),
+ *     ])),
+ * ];
+ * let mut builder = BlobBuilder::new();
+ * builder.push_blob_attrs(&attrs).unwrap();
+ * let parsed = BlobAttr::parse_all(builder.as_slice(), &IFACE).unwrap();
+ * assert_eq!(parsed, attrs);
+ * ```
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlobAttrInfo {
+    pub ty: BlobAttrType,
+    /**
+     * payload length limits, `max_len` 0 means no limit, like in C
+     */
+    pub min_len: usize,
+    pub max_len: usize,
+    /**
+     * the table to parse the children of a `NESTED` attribute with, C leaves this to the caller
+     */
+    pub nested: &'static [BlobAttrInfo],
+}
+
+impl BlobAttrInfo {
+    pub const fn new(ty: BlobAttrType) -> Self {
+        Self {
+            ty,
+            min_len: 0,
+            max_len: 0,
+            nested: &[],
+        }
+    }
+
+    pub const fn nested(table: &'static [BlobAttrInfo]) -> Self {
+        Self {
+            nested: table,
+            ..Self::new(BlobAttrType::NESTED)
+        }
+    }
+
+    pub const fn min_len(self, min_len: usize) -> Self {
+        Self { min_len, ..self }
+    }
+
+    pub const fn max_len(self, max_len: usize) -> Self {
+        Self { max_len, ..self }
+    }
+
+    /* `blob_check_type()` and the length checks of `blob_parse()` */
+    fn check(&self, data: &[u8]) -> Result<(), UbusError> {
+        valid_data!(data.len() >= self.min_len, "Blob attr shorter than min_len");
+        valid_data!(
+            self.max_len == 0 || data.len() <= self.max_len,
+            "Blob attr longer than max_len"
+        );
+        let fixed = match self.ty {
+            BlobAttrType::INT8 => Some(1),
+            BlobAttrType::INT16 => Some(2),
+            BlobAttrType::INT32 => Some(4),
+            BlobAttrType::INT64 | BlobAttrType::DOUBLE => Some(8),
+            _ => None,
+        };
+        valid_data!(
+            fixed.is_none_or(|len| data.len() == len),
+            "Blob attr length doesn't match its type"
+        );
+        if self.ty == BlobAttrType::STRING {
+            valid_data!(data.last() == Some(&0), "String not terminated");
+        }
+        Ok(())
+    }
+}
+
+/**
+ * `BlobAttr` is a plain (not extended) blob, the id means whatever the format using it says
+ *
+ * this is for blobs outside of ubus messages, e.g. procd instance data, netifd internals or `blob_buf`
+ * dumps from ubox, see `BlobAttrInfo` for the type table. Integers are unsigned as `blob_get_u32()` and friends
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobAttr {
+    pub id: u32,
+    pub payload: BlobAttrPayload,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlobAttrPayload {
+    Nested(Vec<BlobAttr>),
+    Binary(Vec<u8>),
+    String(String),
+    Int8(u8),
+    Int16(u16),
+    Int32(u32),
+    Int64(u64),
+    Double(f64),
+}
+
+impl BlobAttrPayload {
+    pub fn blob_type(&self) -> BlobAttrType {
+        match self {
+            BlobAttrPayload::Nested(_) => BlobAttrType::NESTED,
+            BlobAttrPayload::Binary(_) => BlobAttrType::BINARY,
+            BlobAttrPayload::String(_) => BlobAttrType::STRING,
+            BlobAttrPayload::Int8(_) => BlobAttrType::INT8,
+            BlobAttrPayload::Int16(_) => BlobAttrType::INT16,
+            BlobAttrPayload::Int32(_) => BlobAttrType::INT32,
+            BlobAttrPayload::Int64(_) => BlobAttrType::INT64,
+            BlobAttrPayload::Double(_) => BlobAttrType::DOUBLE,
+        }
+    }
+}
+
+impl BlobAttr {
+    pub fn new(id: u32, payload: BlobAttrPayload) -> Self {
+        Self { id, payload }
+    }
+
+    /**
+     * parse all attributes laid one by one in `data`, like `blob_for_each_attr()`
+     */
+    pub fn parse_all(data: &[u8], table: &[BlobAttrInfo]) -> Result<Vec<Self>, UbusError> {
+        Self::parse_all_with_options(data, table, ParseOptions::default())
+    }
+
+    /**
+     * a malformed attribute fails the whole parsing, or is skipped with `ParseOptions::lenient`
+     */
+    pub fn parse_all_with_options(
+        mut data: &[u8],
+        table: &[BlobAttrInfo],
+        options: ParseOptions,
+    ) -> Result<Vec<Self>, UbusError> {
+        let mut attrs = Vec::new();
+        let mut offset = 0;
+        while data.len() >= BlobTag::SIZE {
+            let tag = BlobTag::from_bytes(&data[..BlobTag::SIZE].try_into().unwrap());
+            match Self::from_bytes_with_options(data, table, options) {
+                Ok(attr) => attrs.push(attr),
+                Err(e) if options.lenient && tag.is_valid().is_ok() && tag.size() <= data.len() => {
+                    log::warn!(
+                        "skip malformed blob attr: {}",
+                        e.at(offset, tag.blob_type())
+                    );
+                }
+                Err(e) => {
+                    let e = e.at(offset, tag.blob_type());
+                    if !options.lenient {
+                        return Err(e);
+                    }
+                    log::warn!("drop malformed blob attrs since: {}", e);
+                    break;
+                }
+            }
+            /* the last blob may come without padding */
+            let next_idx = tag.next_tag().min(data.len());
+            data = &data[next_idx..];
+            offset += next_idx;
+        }
+        Ok(attrs)
+    }
+
+    pub fn from_bytes(data: &[u8], table: &[BlobAttrInfo]) -> Result<Self, UbusError> {
+        Self::from_bytes_with_options(data, table, ParseOptions::default())
+    }
+
+    pub fn from_bytes_with_options(
+        data: &[u8],
+        table: &[BlobAttrInfo],
+        options: ParseOptions,
+    ) -> Result<Self, UbusError> {
+        valid_data!(data.len() >= BlobTag::SIZE, "Blob too short");
+        let (tag, data) = data.split_at(BlobTag::SIZE);
+        let tag = BlobTag::from_bytes(tag.try_into().unwrap());
+        tag.is_valid()?;
+        valid_data!(!tag.is_extended(), "Extended blob in blob attrs");
+        valid_data!(data.len() >= tag.inner_len(), "Blob too short");
+        let data = &data[..tag.inner_len()];
+
+        let id = tag.blob_type();
+        let info = table
+            .get(id as usize)
+            .copied()
+            .unwrap_or(BlobAttrInfo::new(BlobAttrType::UNSPEC));
+        info.check(data)?;
+
+        let payload = match info.ty {
+            BlobAttrType::NESTED => BlobAttrPayload::Nested(
+                Self::parse_all_with_options(data, info.nested, options.nested()?)
+                    .map_err(|e| e.at(BlobTag::SIZE, id))?,
+            ),
+            BlobAttrType::STRING => {
+                let bytes = &data[..data.len() - 1];
+                match options.utf8 {
+                    Utf8Policy::Strict => {
+                        BlobAttrPayload::String(core::str::from_utf8(bytes)?.into())
+                    }
+                    Utf8Policy::Lossy => {
+                        BlobAttrPayload::String(String::from_utf8_lossy(bytes).into_owned())
+                    }
+                    /* the NUL is kept in `Binary`, so it's written back as the same C string */
+                    Utf8Policy::Bytes => match core::str::from_utf8(bytes) {
+                        Ok(s) => BlobAttrPayload::String(s.into()),
+                        Err(_) => BlobAttrPayload::Binary(data.to_vec()),
+                    },
+                }
+            }
+            BlobAttrType::INT8 => BlobAttrPayload::Int8(data[0]),
+            BlobAttrType::INT16 => {
+                BlobAttrPayload::Int16(u16::from_be_bytes(data.try_into().unwrap()))
+            }
+            BlobAttrType::INT32 => {
+                BlobAttrPayload::Int32(u32::from_be_bytes(data.try_into().unwrap()))
+            }
+            BlobAttrType::INT64 => {
+                BlobAttrPayload::Int64(u64::from_be_bytes(data.try_into().unwrap()))
+            }
+            BlobAttrType::DOUBLE => {
+                BlobAttrPayload::Double(f64::from_be_bytes(data.try_into().unwrap()))
+            }
+            _ => BlobAttrPayload::Binary(data.to_vec()),
+        };
+        Ok(Self { id, payload })
+    }
+}
+
+/**
+ * the plain blob part of `BlobBuilder`, like `blob_put_u32()`/`blob_nest_start()` in libubox
+ */
+impl<B: BlobBuffer> BlobBuilder<B> {
+    pub fn push_blob_attr(&mut self, attr: &BlobAttr) -> Result<(), UbusError> {
+        let nest = self.open_nest(attr.id)?;
+        match &attr.payload {
+            BlobAttrPayload::Nested(attrs) => self.push_blob_attrs(attrs)?,
+            BlobAttrPayload::Binary(bytes) => self.extend_from_slice(bytes),
+            BlobAttrPayload::String(s) => {
+                self.extend_from_slice(s.as_bytes());
+                self.extend_from_slice(&[0u8]);
+            }
+            BlobAttrPayload::Int8(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobAttrPayload::Int16(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobAttrPayload::Int32(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobAttrPayload::Int64(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobAttrPayload::Double(num) => self.extend_from_slice(&num.to_be_bytes()),
+        }
+        self.close(nest)
+    }
+
+    pub fn push_blob_attrs(&mut self, attrs: &[BlobAttr]) -> Result<(), UbusError> {
+        attrs.iter().try_for_each(|attr| self.push_blob_attr(attr))
+    }
+}
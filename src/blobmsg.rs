@@ -1,11 +1,14 @@
-use std::string::{String, ToString};
-use std::vec;
-use std::vec::Vec;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::{Blob, BlobPayloadParser, BlobTag, UbusError, valid_data, values};
+use crate::{
+    Blob, BlobBuffer, BlobBuilder, BlobIter, BlobNest, BlobPayloadParser, BlobTag, ParseOptions,
+    UbusError, Utf8Policy, valid_data, values,
+};
 
 pub type JsonObject = serde_json::Map<String, Value>;
 
@@ -24,6 +27,63 @@ values!(pub BlobMsgType(u32) {
     DOUBLE = 8,
 });
 
+/**
+ * how JSON integers are encoded, JSON only knows "number" but blobmsg has INT16/INT32/INT64
+ *
+ * C servers check types against their policy (e.g. `BLOBMSG_TYPE_INT32` for `blobmsg_get_u32()`),
+ * so pick the one they expect
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberEncoding {
+    /**
+     * the smallest of INT16/INT32/INT64 that fits
+     */
+    #[default]
+    Narrowest,
+    /**
+     * INT32 if it fits, otherwise INT64, same as `blobmsg_add_json()` of libblobmsg_json
+     */
+    Int32OrInt64,
+    /**
+     * always INT64
+     */
+    Int64,
+}
+
+impl NumberEncoding {
+    fn encode(self, i: i64) -> BlobMsgPayload {
+        match self {
+            NumberEncoding::Narrowest if i16::try_from(i).is_ok() => {
+                BlobMsgPayload::Int16(i as i16)
+            }
+            NumberEncoding::Narrowest | NumberEncoding::Int32OrInt64
+                if i32::try_from(i).is_ok() =>
+            {
+                BlobMsgPayload::Int32(i as i32)
+            }
+            _ => BlobMsgPayload::Int64(i),
+        }
+    }
+
+    /**
+     * widen an INT16/INT32 which is narrower than this encoding gives, wider ones are kept
+     */
+    fn widen(self, payload: &mut BlobMsgPayload) {
+        match (self, &*payload) {
+            (NumberEncoding::Int32OrInt64, BlobMsgPayload::Int16(i)) => {
+                *payload = BlobMsgPayload::Int32((*i).into())
+            }
+            (NumberEncoding::Int64, BlobMsgPayload::Int16(i)) => {
+                *payload = BlobMsgPayload::Int64((*i).into())
+            }
+            (NumberEncoding::Int64, BlobMsgPayload::Int32(i)) => {
+                *payload = BlobMsgPayload::Int64((*i).into())
+            }
+            _ => {}
+        }
+    }
+}
+
 /**
  * `BlobMsg` can represent json, so they can be converted to serde_json::Value and then to string
  */
@@ -38,21 +98,95 @@ pub enum BlobMsgPayload {
     Array(Vec<BlobMsg>),
     Table(Vec<BlobMsg>),
     String(String),
+    /**
+     * a STRING which isn't UTF-8 (see `Utf8Policy::Bytes`), or binary data sent as STRING
+     *
+     * on wire it's the bytes plus a nul, so C sees the length with `blobmsg_data_len()`
+     */
+    Bytes(Vec<u8>),
+    /**
+     * also carries `blobmsg_add_u64()` values, the bits are kept, see `as_u64()`
+     */
     Int64(i64),
     Int32(i32),
     Int16(i16),
+    /**
+     * `BLOBMSG_TYPE_INT8`, only decoded when `ParseOptions::int8_as_bool` is off
+     */
+    Int8(i8),
     Bool(bool),
     Double(f64),
+    /**
+     * json `null`, `BLOBMSG_TYPE_UNSPEC` without payload on wire
+     */
+    Null,
+    /**
+     * type unknown to us, the raw payload is kept and re-encoded unchanged
+     */
     Unknown(u32, Vec<u8>),
 }
 
-/**
- * turn raw bytes into BlobMsg
- *
- */
-impl TryFrom<&[u8]> for BlobMsg {
-    type Error = UbusError;
-    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+impl BlobMsgPayload {
+    /**
+     * the type on wire, `Bytes` are sent as STRING
+     */
+    pub fn blob_type(&self) -> BlobMsgType {
+        match self {
+            BlobMsgPayload::Array(_) => BlobMsgType::ARRAY,
+            BlobMsgPayload::Table(_) => BlobMsgType::TABLE,
+            BlobMsgPayload::String(_) | BlobMsgPayload::Bytes(_) => BlobMsgType::STRING,
+            BlobMsgPayload::Int64(_) => BlobMsgType::INT64,
+            BlobMsgPayload::Int32(_) => BlobMsgType::INT32,
+            BlobMsgPayload::Int16(_) => BlobMsgType::INT16,
+            BlobMsgPayload::Int8(_) => BlobMsgType::INT8,
+            BlobMsgPayload::Bool(_) => BlobMsgType::BOOL,
+            BlobMsgPayload::Double(_) => BlobMsgType::DOUBLE,
+            BlobMsgPayload::Null => BlobMsgType::UNSPEC,
+            BlobMsgPayload::Unknown(id, _) => BlobMsgType(*id),
+        }
+    }
+
+    /**
+     * integers widened to i64, the sign is kept
+     */
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            BlobMsgPayload::Int64(v) => Some(v),
+            BlobMsgPayload::Int32(v) => Some(v.into()),
+            BlobMsgPayload::Int16(v) => Some(v.into()),
+            BlobMsgPayload::Int8(v) => Some(v.into()),
+            BlobMsgPayload::Bool(b) => Some(b.into()),
+            _ => None,
+        }
+    }
+
+    /**
+     * integers read as unsigned of their own width, like `blobmsg_get_u64()`/`blobmsg_get_u32()` in libubox
+     *
+     * blobmsg has no unsigned types, C daemons put e.g. `uint32_t` counters in `INT32`,
+     * so `Int32(-1)` is `u32::MAX` here, not `u64::MAX`
+     */
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            BlobMsgPayload::Int64(v) => Some(v as u64),
+            BlobMsgPayload::Int32(v) => Some((v as u32).into()),
+            BlobMsgPayload::Int16(v) => Some((v as u16).into()),
+            BlobMsgPayload::Int8(v) => Some((v as u8).into()),
+            BlobMsgPayload::Bool(b) => Some(b.into()),
+            _ => None,
+        }
+    }
+}
+
+impl BlobMsg {
+    /**
+     * split raw bytes of a single BlobMsg into its tag, name and payload, nothing is copied
+     *
+     *  BlobTag(4 bytes) + namelen(2 bytes) + name + '\0' + padding to ALIGNMENT + payload
+     *
+     * only the bytes of this BlobMsg are returned, even if `data` contains following blobs
+     */
+    pub fn split_raw(data: &[u8]) -> Result<(BlobTag, &[u8], &[u8]), UbusError> {
         if data.len() < BlobTag::SIZE {
             return Err(UbusError::InvalidData("Data too short to get a BlobTag"));
         }
@@ -62,45 +196,106 @@ impl TryFrom<&[u8]> for BlobMsg {
         if !tag.is_extended() {
             return Err(UbusError::InvalidData("Not an extended blob"));
         }
+        tag.is_valid()?;
+        valid_data!(data.len() >= tag.inner_len(), "Blob too short");
+        /* ISSUE: we must limit the upper bound, if give entire buffer, parsing becomes weird */
+        let data = &data[..tag.inner_len()];
 
+        valid_data!(
+            data.len() >= size_of::<u16>(),
+            "Blob too short to get name length"
+        );
         let (name_len_bytes, data) = data.split_at(size_of::<u16>());
         let name_len = u16::from_be_bytes(name_len_bytes.try_into().unwrap()) as usize;
-        // Get the string
-        if name_len > data.len() {
+        // Get the string, and the nul terminator (implicit)
+        if name_len >= data.len() {
             //eprintln!("name_len:{}, data:{:?}", name_len, data);
             return Err(UbusError::InvalidData("name lenth > data lenth"));
         }
-
-        let (name_bytes, data) = data.split_at(name_len);
-        let name = String::from_utf8(name_bytes.to_vec()).unwrap();
-        // Get the nul terminator (implicit)
-        let name_len = name_len + 1;
-
-        let (terminator, data) = data.split_at(1);
-        valid_data!(terminator[0] == b'\0', "No extended name nul terminator");
+        let (name, data) = data.split_at(name_len);
+        valid_data!(data[0] == b'\0', "No extended name nul terminator");
 
         // Ensure the rest of the payload is aligned
-        let name_total_len = size_of::<u16>() + name_len;
+        let name_total_len = size_of::<u16>() + name_len + 1;
         let name_padding =
             BlobTag::ALIGNMENT.wrapping_sub(name_total_len) & (BlobTag::ALIGNMENT - 1);
-        // FIXME\: maybe not correct
-        /* ISSUE: we must limit the upper bound, if give entire buffer, parsing becomes weird */
-        let parser = BlobPayloadParser::from(&data[name_padding..tag.inner_len() - name_total_len]);
+        valid_data!(data.len() > name_padding, "Blob too short to pad name");
+        Ok((tag, name, &data[1 + name_padding..]))
+    }
+}
+
+/**
+ * turn raw bytes into BlobMsg
+ *
+ */
+impl TryFrom<&[u8]> for BlobMsg {
+    type Error = UbusError;
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        BlobMsg::from_bytes_with_options(data, ParseOptions::default())
+    }
+}
+
+impl BlobMsg {
+    /**
+     * same as `BlobMsg::try_from()`, with limits in `options`
+     */
+    pub fn from_bytes_with_options(data: &[u8], options: ParseOptions) -> Result<Self, UbusError> {
+        BlobMsg::parse(data, options).map_err(|e| {
+            let blob_type = BlobTag::from_slice(data).map_or(0, |tag| tag.blob_type());
+            e.at(0, blob_type)
+        })
+    }
+
+    fn parse(raw: &[u8], options: ParseOptions) -> Result<Self, UbusError> {
+        let (tag, name, data) = BlobMsg::split_raw(raw)?;
+        /* nested errors are located from the start of payload, shift them to the start of this blob */
+        let payload_offset = tag.size() - data.len();
+        let name = match options.utf8 {
+            Utf8Policy::Strict => String::from_utf8(name.to_vec())?,
+            Utf8Policy::Lossy | Utf8Policy::Bytes => String::from_utf8_lossy(name).into_owned(),
+        };
+        let parser = BlobPayloadParser::from(data);
         let data = match BlobMsgType(tag.blob_type()) {
-            BlobMsgType::ARRAY => BlobMsgPayload::Array(parser.try_into()?),
-            BlobMsgType::TABLE => BlobMsgPayload::Table(parser.try_into()?),
-            BlobMsgType::STRING => BlobMsgPayload::String(parser.try_into()?),
+            BlobMsgType::ARRAY => BlobMsgPayload::Array(
+                parse_nested(data, options).map_err(|e| e.at(payload_offset, tag.blob_type()))?,
+            ),
+            BlobMsgType::TABLE => BlobMsgPayload::Table(
+                parse_nested(data, options).map_err(|e| e.at(payload_offset, tag.blob_type()))?,
+            ),
+            BlobMsgType::STRING => {
+                /* strings are nul terminated on wire */
+                let data = data.strip_suffix(b"\0").unwrap_or(data);
+                match (core::str::from_utf8(data), options.utf8) {
+                    (Ok(s), _) => BlobMsgPayload::String(s.into()),
+                    (Err(e), Utf8Policy::Strict) => return Err(e.into()),
+                    (Err(_), Utf8Policy::Lossy) => {
+                        BlobMsgPayload::String(String::from_utf8_lossy(data).into_owned())
+                    }
+                    (Err(_), Utf8Policy::Bytes) => BlobMsgPayload::Bytes(data.to_vec()),
+                }
+            }
             BlobMsgType::INT64 => BlobMsgPayload::Int64(parser.try_into()?),
             BlobMsgType::INT32 => BlobMsgPayload::Int32(parser.try_into()?),
             BlobMsgType::INT16 => BlobMsgPayload::Int16(parser.try_into()?),
-            BlobMsgType::BOOL => BlobMsgPayload::Bool(parser.try_into()?),
+            BlobMsgType::BOOL if options.int8_as_bool => BlobMsgPayload::Bool(parser.try_into()?),
+            BlobMsgType::INT8 => BlobMsgPayload::Int8(parser.try_into()?),
             BlobMsgType::DOUBLE => BlobMsgPayload::Double(parser.try_into()?),
+            BlobMsgType::UNSPEC if data.is_empty() => BlobMsgPayload::Null,
             id => BlobMsgPayload::Unknown(id.value(), parser.into()),
         };
         Ok(BlobMsg { name, data })
     }
 }
 
+/**
+ * parse the payload of a table or array, one level deeper than `options` allows
+ */
+pub(crate) fn parse_nested(data: &[u8], options: ParseOptions) -> Result<Vec<BlobMsg>, UbusError> {
+    BlobIter::with_options(data, options.nested()?)
+        .map(|blob| blob?.try_into())
+        .try_collect()
+}
+
 /**
  * turn a single BlobMsg into bytes
  * normally BlobMsg should appear as a Vec<BlobMsg>, and here exists a helper struct MsgTable is defined to represents it
@@ -110,8 +305,106 @@ impl TryFrom<&[u8]> for BlobMsg {
 impl TryFrom<BlobMsg> for Vec<u8> {
     type Error = UbusError;
     fn try_from(blobmsg: BlobMsg) -> Result<Self, Self::Error> {
-        let builder = BlobMsgBuilder::try_from(blobmsg)?;
-        Ok(builder.data())
+        let mut builder = BlobBuilder::new();
+        builder.push_blobmsg(&blobmsg)?;
+        Ok(builder.into())
+    }
+}
+
+/**
+ * the blobmsg part of `BlobBuilder`, like `blobmsg_open_table()`/`blobmsg_close_table()` in libubox
+ *
+ * ```
+ * use ubus::{BlobBuilder, BlobMsgPayload};
+ * let mut builder = BlobBuilder::new();
+ * let table = builder.open_table("statistics").unwrap();
+ * builder.push_blobmsg_payload("rx_bytes", &BlobMsgPayload::Int64(1024)).unwrap();
+ * builder.close(table).unwrap();
+ * ```
+ */
+impl<B: BlobBuffer> BlobBuilder<B> {
+    pub fn open_table(&mut self, name: &str) -> Result<BlobNest, UbusError> {
+        self.open_blobmsg(BlobMsgType::TABLE, name)
+    }
+
+    /**
+     * the names of elements pushed into array are ignored by ubusd, use `""`
+     */
+    pub fn open_array(&mut self, name: &str) -> Result<BlobNest, UbusError> {
+        self.open_blobmsg(BlobMsgType::ARRAY, name)
+    }
+
+    /**
+     * write the extended tag and the name, payload goes next and ends with `close()`
+     *
+     *  BlobTag(4 bytes) + namelen(2 bytes) + name + '\0' + padding to ALIGNMENT
+     */
+    pub fn open_blobmsg(&mut self, id: BlobMsgType, name: &str) -> Result<BlobNest, UbusError> {
+        let name_len =
+            u16::try_from(name.len()).map_err(|_| UbusError::InvalidData("Name too long"))?;
+        let nest = self.open_tag(id.value(), true)?;
+        self.extend_from_slice(&name_len.to_be_bytes());
+        self.extend_from_slice(name.as_bytes());
+        let name_total_len = size_of::<u16>() + name.len() + 1;
+        let name_padding =
+            BlobTag::ALIGNMENT.wrapping_sub(name_total_len) & (BlobTag::ALIGNMENT - 1);
+        self.extend_from_slice(&[0u8; 1 + BlobTag::ALIGNMENT][..1 + name_padding]);
+        Ok(nest)
+    }
+
+    pub fn push_blobmsg(&mut self, blobmsg: &BlobMsg) -> Result<(), UbusError> {
+        self.push_blobmsg_payload(&blobmsg.name, &blobmsg.data)
+    }
+
+    pub fn push_blobmsg_payload(
+        &mut self,
+        name: &str,
+        payload: &BlobMsgPayload,
+    ) -> Result<(), UbusError> {
+        match payload {
+            BlobMsgPayload::Array(list) => {
+                let nest = self.open_array(name)?;
+                list.iter()
+                    .try_for_each(|blobmsg| self.push_blobmsg(blobmsg))?;
+                return self.close(nest);
+            }
+            BlobMsgPayload::Table(table) => {
+                let nest = self.open_table(name)?;
+                self.push_msg_table(table)?;
+                return self.close(nest);
+            }
+            _ => {}
+        }
+        let nest = self.open_blobmsg(payload.blob_type(), name)?;
+        match payload {
+            BlobMsgPayload::String(s) => {
+                self.extend_from_slice(s.as_bytes());
+                self.extend_from_slice(&[0u8]);
+            }
+            BlobMsgPayload::Bytes(bytes) => {
+                self.extend_from_slice(bytes);
+                self.extend_from_slice(&[0u8]);
+            }
+            BlobMsgPayload::Int64(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobMsgPayload::Int32(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobMsgPayload::Int16(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobMsgPayload::Int8(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobMsgPayload::Bool(b) => self.extend_from_slice(&[*b as u8]),
+            BlobMsgPayload::Double(num) => self.extend_from_slice(&num.to_be_bytes()),
+            BlobMsgPayload::Null => {}
+            BlobMsgPayload::Unknown(_, bytes) => self.extend_from_slice(bytes),
+            _ => unreachable!(),
+        }
+        self.close(nest)
+    }
+
+    /**
+     * push BlobMsgs of the table one by one, without a container
+     */
+    pub fn push_msg_table(&mut self, table: &[BlobMsg]) -> Result<(), UbusError> {
+        table
+            .iter()
+            .try_for_each(|blobmsg| self.push_blobmsg(blobmsg))
     }
 }
 
@@ -120,24 +413,29 @@ impl TryFrom<BlobMsg> for Vec<u8> {
  */
 impl From<Value> for BlobMsgPayload {
     fn from(json_value: Value) -> Self {
+        BlobMsgPayload::from_json(json_value, NumberEncoding::default())
+    }
+}
+
+impl BlobMsgPayload {
+    /**
+     * same as `BlobMsgPayload::from()`, integers are encoded as `numbers` says
+     */
+    pub fn from_json(json_value: Value, numbers: NumberEncoding) -> Self {
         match json_value {
-            Value::Null => BlobMsgPayload::Unknown(0, vec![]),
+            Value::Null => BlobMsgPayload::Null,
 
             Value::Bool(b) => BlobMsgPayload::Bool(b),
 
             Value::Number(num) => {
                 if let Some(i) = num.as_i64() {
-                    if i <= i16::MAX as i64 && i >= i16::MIN as i64 {
-                        BlobMsgPayload::Int16(i as i16)
-                    } else if i <= i32::MAX as i64 && i >= i32::MIN as i64 {
-                        BlobMsgPayload::Int32(i as i32)
-                    } else {
-                        BlobMsgPayload::Int64(i)
-                    }
-                } else if let Some(f) = num.as_f64() {
-                    BlobMsgPayload::Double(f)
+                    numbers.encode(i)
+                } else if let Some(u) = num.as_u64() {
+                    /* above i64::MAX, keep the bits like `blobmsg_add_u64()`, C reads it back with `blobmsg_get_u64()` */
+                    BlobMsgPayload::Int64(u as i64)
                 } else {
-                    BlobMsgPayload::Unknown(1, vec![])
+                    /* serde_json numbers are always one of i64, u64 and f64 */
+                    BlobMsgPayload::Double(num.as_f64().unwrap_or(f64::NAN))
                 }
             }
 
@@ -147,7 +445,7 @@ impl From<Value> for BlobMsgPayload {
                 arr.into_iter()
                     .map(|v| BlobMsg {
                         name: "".into(),
-                        data: BlobMsgPayload::from(v),
+                        data: BlobMsgPayload::from_json(v, numbers),
                     })
                     .collect(),
             ),
@@ -156,7 +454,7 @@ impl From<Value> for BlobMsgPayload {
                 map.into_iter()
                     .map(|(k, v)| BlobMsg {
                         name: k,
-                        data: BlobMsgPayload::from(v),
+                        data: BlobMsgPayload::from_json(v, numbers),
                     })
                     .collect::<Vec<BlobMsg>>(),
             ),
@@ -167,9 +465,26 @@ impl From<Value> for BlobMsgPayload {
 impl TryFrom<BlobMsgPayload> for Value {
     type Error = UbusError;
     fn try_from(blobmsg_payload: BlobMsgPayload) -> Result<Self, Self::Error> {
-        Ok(match blobmsg_payload {
+        blobmsg_payload.to_json_with(JsonOptions::default())
+    }
+}
+
+impl BlobMsgPayload {
+    /**
+     * same as `Value::try_from()`, names appearing twice in (nested) tables are handled as `duplicates` says
+     */
+    pub fn to_json(self, duplicates: DuplicateNames) -> Result<Value, UbusError> {
+        self.to_json_with(JsonOptions::default().duplicates(duplicates))
+    }
+
+    /**
+     * same as `to_json()`, with `options` for bytes as well, see `JsonOptions`
+     */
+    pub fn to_json_with(self, options: JsonOptions) -> Result<Value, UbusError> {
+        Ok(match self {
             BlobMsgPayload::Bool(b) => Value::Bool(b),
             BlobMsgPayload::Int16(v) => Value::Number(v.into()),
+            BlobMsgPayload::Int8(v) => Value::Number(v.into()),
             BlobMsgPayload::Int32(v) => Value::Number(v.into()),
             BlobMsgPayload::Int64(v) => Value::Number(v.into()),
             BlobMsgPayload::Double(f) => Value::Number(
@@ -178,19 +493,21 @@ impl TryFrom<BlobMsgPayload> for Value {
             ),
 
             BlobMsgPayload::String(s) => Value::String(s),
+            BlobMsgPayload::Bytes(bytes) => Value::String(match options.bytes {
+                BytesEncoding::Base64 => base64_encode(&bytes),
+                BytesEncoding::Lossy => String::from_utf8_lossy(&bytes).into_owned(),
+            }),
+            BlobMsgPayload::Null => Value::Null,
 
             BlobMsgPayload::Array(arr) => Value::Array(
                 arr.into_iter()
-                    .map(|blobmsg| blobmsg.data.try_into())
+                    .map(|blobmsg| blobmsg.data.to_json_with(options))
                     .try_collect::<Vec<Value>>()?,
             ),
 
-            BlobMsgPayload::Table(map) => Value::Object(
-                map.into_iter()
-                    .map(|blobmsg| Ok::<_, UbusError>((blobmsg.name, blobmsg.data.try_into()?)))
-                    .try_collect::<serde_json::Map<String, Value>>()?
-                    .into(),
-            ),
+            BlobMsgPayload::Table(map) => {
+                Value::Object(MsgTable(map).to_json_object_with(options)?)
+            }
 
             BlobMsgPayload::Unknown(_, _) => {
                 return Err(UbusError::InvalidData("Unknown blob type"));
@@ -199,6 +516,10 @@ impl TryFrom<BlobMsgPayload> for Value {
     }
 }
 
+/**
+ * transparent, so nested tables in `BlobMsgPayload` can be borrowed as `MsgTable`, see `MsgTable::from_vec()`
+ */
+#[repr(transparent)]
 #[derive(Clone)]
 pub struct MsgTable(pub Vec<BlobMsg>);
 impl MsgTable {
@@ -213,6 +534,15 @@ impl MsgTable {
         // String::try_from(self)
         self.clone().try_into()
     }
+    /**
+     * append the encoded BlobMsgs to `buffer`, the table is not consumed nor cloned
+     */
+    pub fn encode_into<B: BlobBuffer>(&self, buffer: &mut B) -> Result<(), UbusError> {
+        let mut builder = BlobBuilder::with_buffer(core::mem::take(buffer));
+        let result = builder.push_msg_table(&self.0);
+        *buffer = builder.into_inner();
+        result
+    }
     pub fn to_string_pretty(self) -> Result<String, UbusError> {
         // String::try_from(self)
         Ok(serde_json::to_string_pretty(&JsonObject::try_from(self)?)
@@ -235,21 +565,22 @@ impl core::fmt::Debug for MsgTable {
     }
 }
 
-impl TryFrom<MsgTable> for Vec<u8> {
+impl TryFrom<&MsgTable> for Vec<u8> {
     type Error = UbusError;
     /**
      * turn Vec<BlobMsg> into bytes
-     * Real magic happens on `BlobMsg::try_into(Vec<u8>)` -> `BlobMsgBuilder::try_from()`
+     * Real magic happens on `BlobBuilder::push_blobmsg()`
      */
+    fn try_from(msgtable: &MsgTable) -> Result<Self, Self::Error> {
+        let mut buffer = Vec::new();
+        msgtable.encode_into(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+impl TryFrom<MsgTable> for Vec<u8> {
+    type Error = UbusError;
     fn try_from(msgtable: MsgTable) -> Result<Self, Self::Error> {
-        Ok(msgtable
-            .0
-            .into_iter()
-            .map(|blobmsg| <Vec<u8>>::try_from(blobmsg))
-            .try_collect::<Vec<Vec<u8>>>()?
-            .into_iter()
-            .flatten()
-            .collect::<Vec<u8>>())
+        (&msgtable).try_into()
     }
 }
 impl From<Vec<BlobMsg>> for MsgTable {
@@ -262,45 +593,84 @@ impl TryFrom<Blob> for BlobMsg {
     fn try_from(blob: Blob) -> Result<Self, Self::Error> {
         match blob {
             Blob::BlogMsg(blobmsg) => Ok(blobmsg),
-            Blob::UbusBlob(_) => Err(UbusError::InvalidData("")),
+            Blob::UbusBlob(_) => Err(UbusError::InvalidData(
+                "UbusBlob found where BlobMsg expected",
+            )),
         }
     }
 }
 impl TryFrom<&str> for MsgTable {
     type Error = UbusError;
     fn try_from(json: &str) -> Result<Self, Self::Error> {
-        /* empty string is legal */
-        if json.is_empty() {
-            return Ok(MsgTable::new());
-        }
-        // top-level MUST be object/array to produce args
-        serde_json::from_str::<Value>(json)?.try_into()
+        MsgTable::from_json(json, NumberEncoding::default())
     }
 }
 impl TryFrom<JsonObject> for MsgTable {
     type Error = UbusError;
     fn try_from(json_map: JsonObject) -> Result<Self, Self::Error> {
-        Ok(MsgTable(
-            json_map
-                .into_iter()
-                .map(|(k, v)| BlobMsg {
-                    name: k,
-                    data: v.into(),
-                })
-                .collect(),
-        ))
+        MsgTable::from_json_value(Value::Object(json_map), NumberEncoding::default())
     }
 }
 impl TryFrom<Value> for MsgTable {
     type Error = UbusError;
     fn try_from(json_value: Value) -> Result<Self, Self::Error> {
+        MsgTable::from_json_value(json_value, NumberEncoding::default())
+    }
+}
+impl MsgTable {
+    /**
+     * same as `MsgTable::try_from(json)`, integers are encoded as `numbers` says
+     *
+     * ```
+     * use ubus::{BlobMsgPayload, MsgTable, NumberEncoding};
+     * let table = MsgTable::from_json(r#"{"port": 80}"#, NumberEncoding::Int32OrInt64).unwrap();
+     * assert!(matches!(table.0[0].data, BlobMsgPayload::Int32(80)));
+     * ```
+     */
+    pub fn from_json(json: &str, numbers: NumberEncoding) -> Result<Self, UbusError> {
+        /* empty string is legal */
+        if json.is_empty() {
+            return Ok(MsgTable::new());
+        }
+        // top-level MUST be object/array to produce args
+        MsgTable::from_json_value(serde_json::from_str::<Value>(json)?, numbers)
+    }
+
+    pub fn from_json_value(json_value: Value, numbers: NumberEncoding) -> Result<Self, UbusError> {
         match json_value {
-            Value::Object(map) => map.try_into(),
+            Value::Object(map) => Ok(MsgTable(
+                map.into_iter()
+                    .map(|(k, v)| BlobMsg {
+                        name: k,
+                        data: BlobMsgPayload::from_json(v, numbers),
+                    })
+                    .collect(),
+            )),
             _ => Err(UbusError::InvalidData(
                 "Invalid JSON, must be object at top-level",
             )),
         }
     }
+
+    /**
+     * widen INT16/INT32 (also in nested tables and arrays) up to what `numbers` gives,
+     * e.g. a table converted with `NumberEncoding::Narrowest` becomes acceptable to `Int32OrInt64` servers
+     *
+     * integers are never narrowed, an explicit INT64 may be what the other side's policy asks for
+     */
+    pub fn widen_numbers(&mut self, numbers: NumberEncoding) {
+        fn widen(blobmsgs: &mut [BlobMsg], numbers: NumberEncoding) {
+            for blobmsg in blobmsgs {
+                match &mut blobmsg.data {
+                    BlobMsgPayload::Array(nested) | BlobMsgPayload::Table(nested) => {
+                        widen(nested, numbers)
+                    }
+                    payload => numbers.widen(payload),
+                }
+            }
+        }
+        widen(&mut self.0, numbers)
+    }
 }
 
 impl TryFrom<MsgTable> for String {
@@ -313,14 +683,130 @@ impl TryFrom<MsgTable> for String {
 impl TryFrom<MsgTable> for JsonObject {
     type Error = UbusError;
     fn try_from(value: MsgTable) -> Result<Self, Self::Error> {
-        value
-            .0
-            .into_iter()
-            .try_fold(JsonObject::new(), |mut obj, msg| {
-                let (k, v) = (msg.name, msg.data.try_into()?);
-                obj.insert(k, v);
-                Ok(obj)
-            })
+        value.to_json_object_with(JsonOptions::default())
+    }
+}
+
+/**
+ * how `BlobMsgPayload::Bytes` is put in JSON, which only has (unicode) strings
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BytesEncoding {
+    /**
+     * standard base64 with padding, lossless but not readable
+     *
+     * the result is a plain JSON string, so `from_json()` reads it back as a `String`, not the bytes
+     */
+    #[default]
+    Base64,
+    /**
+     * invalid sequences replaced with U+FFFD, readable but lossy
+     */
+    Lossy,
+}
+
+/**
+ * how BlobMsgs are turned into JSON, for what JSON can't express directly
+ *
+ * JSON doesn't tell what it came from, e.g. base64 bytes look like any other string,
+ * so the result doesn't round-trip through `from_json()` when such things are involved
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOptions {
+    pub duplicates: DuplicateNames,
+    pub bytes: BytesEncoding,
+}
+
+impl JsonOptions {
+    /**
+     * names appearing twice in a table, `DuplicateNames::KeepLast` by default
+     */
+    pub fn duplicates(self, duplicates: DuplicateNames) -> Self {
+        Self { duplicates, ..self }
+    }
+
+    /**
+     * STRINGs which aren't UTF-8, `BytesEncoding::Base64` by default, which gives an ordinary JSON string
+     */
+    pub fn bytes(self, bytes: BytesEncoding) -> Self {
+        Self { bytes, ..self }
+    }
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (idx, b)| bits | (*b as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * idx)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/**
+ * blobmsg tables may have the same name twice, but JSON objects (and `JsonObject`) can't
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateNames {
+    /**
+     * the last value wins, at the position of the first one
+     */
+    #[default]
+    KeepLast,
+    /**
+     * all values are collected into an array, in wire order
+     */
+    KeepAll,
+    /**
+     * fail with `UbusError::DuplicateName`
+     */
+    Error,
+}
+
+impl MsgTable {
+    /**
+     * same as `JsonObject::try_from()`, keys are in wire order, duplicated names are handled as `duplicates` says
+     */
+    pub fn to_json_object(self, duplicates: DuplicateNames) -> Result<JsonObject, UbusError> {
+        self.to_json_object_with(JsonOptions::default().duplicates(duplicates))
+    }
+
+    /**
+     * same as `to_json_object()`, with `options` for bytes as well, see `JsonOptions`
+     */
+    pub fn to_json_object_with(self, options: JsonOptions) -> Result<JsonObject, UbusError> {
+        let duplicates = options.duplicates;
+        let mut obj = JsonObject::new();
+        /* names already turned into arrays by `KeepAll` */
+        let mut collected: Vec<String> = Vec::new();
+        for blobmsg in self.0 {
+            let value = blobmsg.data.to_json_with(options)?;
+            let Some(old) = obj.get_mut(&blobmsg.name) else {
+                obj.insert(blobmsg.name, value);
+                continue;
+            };
+            match duplicates {
+                DuplicateNames::KeepLast => *old = value,
+                DuplicateNames::Error => return Err(UbusError::DuplicateName(blobmsg.name)),
+                DuplicateNames::KeepAll => match old {
+                    Value::Array(values) if collected.contains(&blobmsg.name) => values.push(value),
+                    _ => {
+                        *old = Value::Array(vec![old.take(), value]);
+                        collected.push(blobmsg.name);
+                    }
+                },
+            }
+        }
+        Ok(obj)
     }
 }
 /* TODO: use something like Map<String, Map<String, BlobMsgType>> to describe UbusBlob::Signature */
@@ -342,6 +828,9 @@ impl TryFrom<BlobMsg> for BlobMsgBuilder {
         let name = blobmsg.name;
         match blobmsg.data {
             BlobMsgPayload::String(s) => BlobMsgBuilder::from_str(BlobMsgType::STRING, &name, &s),
+            BlobMsgPayload::Bytes(bytes) => {
+                BlobMsgBuilder::from_bytes(BlobMsgType::STRING, &name, bytes.iter().chain(&[0u8]))
+            }
             BlobMsgPayload::Int64(num) => {
                 BlobMsgBuilder::from_int64(BlobMsgType::INT64, &name, num)
             }
@@ -351,18 +840,22 @@ impl TryFrom<BlobMsg> for BlobMsgBuilder {
             BlobMsgPayload::Int16(num) => {
                 BlobMsgBuilder::from_int16(BlobMsgType::INT16, &name, num)
             }
+            BlobMsgPayload::Int8(num) => {
+                BlobMsgBuilder::from_bytes(BlobMsgType::INT8, &name, &num.to_be_bytes())
+            }
             BlobMsgPayload::Double(num) => {
                 BlobMsgBuilder::from_double(BlobMsgType::DOUBLE, &name, num)
             }
             BlobMsgPayload::Bool(b) => BlobMsgBuilder::from_bool(BlobMsgType::BOOL, &name, b),
-            BlobMsgPayload::Unknown(_typeid, _bytes) => {
-                //println!("\"type={} data={:?}\"", typeid, bytes);
-                unimplemented!()
+            BlobMsgPayload::Null => Ok(BlobMsgBuilder::new_extended(BlobMsgType::UNSPEC, &name)),
+            BlobMsgPayload::Unknown(typeid, bytes) => {
+                valid_data!(typeid <= 0x7f, "Blob type out of range");
+                BlobMsgBuilder::from_bytes(BlobMsgType(typeid), &name, &bytes)
             }
             BlobMsgPayload::Array(list) => {
                 let mut builder = BlobMsgBuilder::new_extended(BlobMsgType::ARRAY, &name);
                 for blobmsg in list {
-                    let inner_builder = BlobMsgBuilder::try_from(blobmsg).unwrap();
+                    let inner_builder = BlobMsgBuilder::try_from(blobmsg)?;
                     builder.push_bytes(inner_builder.data_as_slice())?;
                 }
                 Ok(builder)
@@ -370,7 +863,7 @@ impl TryFrom<BlobMsg> for BlobMsgBuilder {
             BlobMsgPayload::Table(table) => {
                 let mut builder = BlobMsgBuilder::new_extended(BlobMsgType::TABLE, &name);
                 for blobmsg in table {
-                    let inner_builder = BlobMsgBuilder::try_from(blobmsg).unwrap();
+                    let inner_builder = BlobMsgBuilder::try_from(blobmsg)?;
                     builder.push_bytes(inner_builder.data_as_slice())?;
                 }
                 Ok(builder)
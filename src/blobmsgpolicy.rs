@@ -0,0 +1,104 @@
+use alloc::string::ToString;
+
+use crate::{BlobMsg, BlobMsgRef, BlobMsgType, MsgTable, MsgTableRef, UbusError};
+
+/**
+ * `BlobMsgPolicy` is `struct blobmsg_policy` in libubox, a name and the type it must have
+ *
+ * a policy is usually a `static` array, parsing gives one slot for each entry in the same order,
+ * like `blobmsg_parse()` filling `tb[]`
+ *
+ * ```
+ * use ubus::{BlobMsgPolicy, BlobMsgType, MsgTable};
+ * static POLICY: [BlobMsgPolicy; 3] = [
+ *     BlobMsgPolicy::new("interface", BlobMsgType::STRING),
+ *     BlobMsgPolicy::new("up", BlobMsgType::BOOL),
+ *     BlobMsgPolicy::new("data", BlobMsgType::UNSPEC),
+ * ];
+ * let event = MsgTable::try_from(r#"{"interface": "wan", "up": true}"#).unwrap();
+ * let [interface, up, data] = event.parse_policy(&POLICY).unwrap();
+ * assert_eq!(interface.unwrap().data.as_str(), Some("wan"));
+ * assert_eq!(up.unwrap().data.as_bool(), Some(true));
+ * assert!(data.is_none());
+ * ```
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct BlobMsgPolicy {
+    pub name: &'static str,
+    /**
+     * `BlobMsgType::UNSPEC` accepts any type
+     */
+    pub ty: BlobMsgType,
+}
+
+impl BlobMsgPolicy {
+    pub const fn new(name: &'static str, ty: BlobMsgType) -> Self {
+        Self { name, ty }
+    }
+
+    fn check(&self, found: BlobMsgType) -> Result<(), UbusError> {
+        if self.ty == BlobMsgType::UNSPEC || self.ty == found {
+            Ok(())
+        } else {
+            Err(UbusError::PolicyMismatch {
+                name: self.name.to_string(),
+                expected: self.ty,
+                found,
+            })
+        }
+    }
+}
+
+impl MsgTable {
+    /**
+     * pick BlobMsgs named in `policy` in one pass, same as `blobmsg_parse()`
+     *
+     * a slot is `None` if the name is missing, the first one wins if a name appears twice,
+     * and a name with the wrong type fails with `UbusError::PolicyMismatch` (C skips it silently)
+     */
+    pub fn parse_policy<const N: usize>(
+        &self,
+        policy: &[BlobMsgPolicy; N],
+    ) -> Result<[Option<&BlobMsg>; N], UbusError> {
+        let mut slots = [None; N];
+        for blobmsg in &self.0 {
+            if let Some(idx) = slot_of(policy, &slots, &blobmsg.name) {
+                policy[idx].check(blobmsg.data.blob_type())?;
+                slots[idx] = Some(blobmsg);
+            }
+        }
+        Ok(slots)
+    }
+}
+
+impl<'a> MsgTableRef<'a> {
+    /**
+     * same as `MsgTable::parse_policy()` without copying, malformed blobs fail the parsing
+     */
+    pub fn parse_policy<const N: usize>(
+        &self,
+        policy: &[BlobMsgPolicy; N],
+    ) -> Result<[Option<BlobMsgRef<'a>>; N], UbusError> {
+        let mut slots = [None; N];
+        for blobmsg in self.iter() {
+            let blobmsg = blobmsg?;
+            if let Some(idx) = slot_of(policy, &slots, blobmsg.name) {
+                policy[idx].check(blobmsg.data.blob_type())?;
+                slots[idx] = Some(blobmsg);
+            }
+        }
+        Ok(slots)
+    }
+}
+
+/* the first empty slot whose policy is for `name` */
+fn slot_of<T, const N: usize>(
+    policy: &[BlobMsgPolicy; N],
+    slots: &[Option<T>; N],
+    name: &str,
+) -> Option<usize> {
+    policy
+        .iter()
+        .zip(slots)
+        .position(|(policy, slot)| slot.is_none() && policy.name == name)
+}
@@ -0,0 +1,318 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{
+    BlobMsg, BlobMsgPayload, BlobMsgType, BlobPayloadParser, BlobTag, MsgTable, ParseOptions,
+    UbusError, Utf8Policy,
+};
+
+/**
+ * `BlobMsgRef` is the borrowed version of `BlobMsg`, it points into the received buffer
+ *
+ * nothing is allocated while walking, names and strings are `&str`, nested tables are `MsgTableRef`
+ * which are only parsed when iterated, use `BlobMsgRef::to_blob_msg()` to get an owned copy on demand
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct BlobMsgRef<'a> {
+    pub name: &'a str,
+    pub data: BlobMsgPayloadRef<'a>,
+    /* options the view was decoded with, kept for `to_blob_msg()` */
+    options: ParseOptions,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BlobMsgPayloadRef<'a> {
+    Array(MsgTableRef<'a>),
+    Table(MsgTableRef<'a>),
+    String(&'a str),
+    /**
+     * a STRING which isn't UTF-8, only with `Utf8Policy::Lossy` or `Utf8Policy::Bytes`, without the nul
+     */
+    Bytes(&'a [u8]),
+    Int64(i64),
+    Int32(i32),
+    Int16(i16),
+    Int8(i8),
+    Bool(bool),
+    Double(f64),
+    Null,
+    Unknown(u32, &'a [u8]),
+}
+
+impl<'a> TryFrom<&'a [u8]> for BlobMsgRef<'a> {
+    type Error = UbusError;
+    fn try_from(data: &'a [u8]) -> Result<Self, Self::Error> {
+        BlobMsgRef::from_bytes_with_options(data, ParseOptions::default())
+    }
+}
+
+impl<'a> BlobMsgRef<'a> {
+    /**
+     * same as `BlobMsgRef::try_from()`, nested tables keep `options` for when they are iterated
+     *
+     * names are `&str`, so they must be UTF-8 whatever `options.utf8` says
+     */
+    pub fn from_bytes_with_options(
+        data: &'a [u8],
+        options: ParseOptions,
+    ) -> Result<Self, UbusError> {
+        let (tag, name, data) = BlobMsg::split_raw(data)?;
+        /* the payload is what's left of the blob after its tag and name */
+        let payload_offset = BlobTag::SIZE + tag.inner_len() - data.len();
+        let name = core::str::from_utf8(name)?;
+        let parser = BlobPayloadParser::from(data);
+        let data = match BlobMsgType(tag.blob_type()) {
+            BlobMsgType::ARRAY => {
+                BlobMsgPayloadRef::Array(MsgTableRef(data, options, payload_offset))
+            }
+            BlobMsgType::TABLE => {
+                BlobMsgPayloadRef::Table(MsgTableRef(data, options, payload_offset))
+            }
+            BlobMsgType::STRING => {
+                /* strings are nul terminated on wire */
+                let data = data.strip_suffix(b"\0").unwrap_or(data);
+                match core::str::from_utf8(data) {
+                    Ok(s) => BlobMsgPayloadRef::String(s),
+                    Err(e) if options.utf8 == Utf8Policy::Strict => return Err(e.into()),
+                    Err(_) => BlobMsgPayloadRef::Bytes(data),
+                }
+            }
+            BlobMsgType::INT64 => BlobMsgPayloadRef::Int64(parser.try_into()?),
+            BlobMsgType::INT32 => BlobMsgPayloadRef::Int32(parser.try_into()?),
+            BlobMsgType::INT16 => BlobMsgPayloadRef::Int16(parser.try_into()?),
+            BlobMsgType::BOOL if options.int8_as_bool => {
+                BlobMsgPayloadRef::Bool(parser.try_into()?)
+            }
+            BlobMsgType::INT8 => BlobMsgPayloadRef::Int8(parser.try_into()?),
+            BlobMsgType::DOUBLE => BlobMsgPayloadRef::Double(parser.try_into()?),
+            BlobMsgType::UNSPEC if data.is_empty() => BlobMsgPayloadRef::Null,
+            id => BlobMsgPayloadRef::Unknown(id.value(), data),
+        };
+        Ok(BlobMsgRef {
+            name,
+            data,
+            options,
+        })
+    }
+
+    /**
+     * decoded with the options the view was made with, e.g. a `Bytes` under `Utf8Policy::Lossy` becomes a `String`
+     */
+    pub fn to_blob_msg(&self) -> Result<BlobMsg, UbusError> {
+        (*self).try_into()
+    }
+
+    /**
+     * same as `to_blob_msg()`, with `options` instead, nested tables are limited by `options.max_depth`
+     */
+    pub fn to_blob_msg_with_options(&self, options: ParseOptions) -> Result<BlobMsg, UbusError> {
+        Ok(BlobMsg {
+            name: self.name.into(),
+            data: self.data.to_payload_with_options(options)?,
+        })
+    }
+}
+
+impl<'a> TryFrom<BlobMsgRef<'a>> for BlobMsg {
+    type Error = UbusError;
+    fn try_from(blobmsg: BlobMsgRef<'a>) -> Result<Self, Self::Error> {
+        blobmsg.to_blob_msg_with_options(blobmsg.options)
+    }
+}
+
+impl<'a> TryFrom<BlobMsgPayloadRef<'a>> for BlobMsgPayload {
+    type Error = UbusError;
+    fn try_from(payload: BlobMsgPayloadRef<'a>) -> Result<Self, Self::Error> {
+        payload.to_payload_with_options(payload.options())
+    }
+}
+
+impl<'a> BlobMsgPayloadRef<'a> {
+    /**
+     * the type on wire, see `BlobMsgPayload::blob_type()`
+     */
+    pub fn blob_type(&self) -> BlobMsgType {
+        match self {
+            BlobMsgPayloadRef::Array(_) => BlobMsgType::ARRAY,
+            BlobMsgPayloadRef::Table(_) => BlobMsgType::TABLE,
+            BlobMsgPayloadRef::String(_) | BlobMsgPayloadRef::Bytes(_) => BlobMsgType::STRING,
+            BlobMsgPayloadRef::Int64(_) => BlobMsgType::INT64,
+            BlobMsgPayloadRef::Int32(_) => BlobMsgType::INT32,
+            BlobMsgPayloadRef::Int16(_) => BlobMsgType::INT16,
+            BlobMsgPayloadRef::Int8(_) => BlobMsgType::INT8,
+            BlobMsgPayloadRef::Bool(_) => BlobMsgType::BOOL,
+            BlobMsgPayloadRef::Double(_) => BlobMsgType::DOUBLE,
+            BlobMsgPayloadRef::Null => BlobMsgType::UNSPEC,
+            BlobMsgPayloadRef::Unknown(id, _) => BlobMsgType(*id),
+        }
+    }
+
+    /* options the view was decoded with, only nested tables remember them, see `BlobMsgRef::to_blob_msg()` for scalars */
+    fn options(&self) -> ParseOptions {
+        match self {
+            BlobMsgPayloadRef::Array(table) | BlobMsgPayloadRef::Table(table) => table.1,
+            _ => ParseOptions::default(),
+        }
+    }
+
+    pub fn to_payload_with_options(
+        self,
+        options: ParseOptions,
+    ) -> Result<BlobMsgPayload, UbusError> {
+        Ok(match self {
+            BlobMsgPayloadRef::Array(table) => {
+                BlobMsgPayload::Array(table.to_msg_table_with_options(options)?.0)
+            }
+            BlobMsgPayloadRef::Table(table) => {
+                BlobMsgPayload::Table(table.to_msg_table_with_options(options)?.0)
+            }
+            BlobMsgPayloadRef::String(s) => BlobMsgPayload::String(String::from(s)),
+            BlobMsgPayloadRef::Bytes(bytes) => match options.utf8 {
+                Utf8Policy::Lossy => BlobMsgPayload::String(String::from_utf8_lossy(bytes).into()),
+                Utf8Policy::Strict | Utf8Policy::Bytes => BlobMsgPayload::Bytes(bytes.to_vec()),
+            },
+            BlobMsgPayloadRef::Int64(v) => BlobMsgPayload::Int64(v),
+            BlobMsgPayloadRef::Int32(v) => BlobMsgPayload::Int32(v),
+            BlobMsgPayloadRef::Int16(v) => BlobMsgPayload::Int16(v),
+            BlobMsgPayloadRef::Int8(v) => BlobMsgPayload::Int8(v),
+            BlobMsgPayloadRef::Bool(v) => BlobMsgPayload::Bool(v),
+            BlobMsgPayloadRef::Double(v) => BlobMsgPayload::Double(v),
+            BlobMsgPayloadRef::Null => BlobMsgPayload::Null,
+            BlobMsgPayloadRef::Unknown(id, bytes) => BlobMsgPayload::Unknown(id, bytes.to_vec()),
+        })
+    }
+}
+
+/**
+ * `MsgTableRef` is the borrowed version of `MsgTable`, a view of BlobMsgs laid one by one in bytes
+ *
+ * it's only a slice, so it's cheap to copy around, parsing happens lazily in `iter()`
+ *
+ * a nested table also knows where it starts in the BlobMsg holding it, to locate errors inside
+ */
+#[derive(Clone, Copy, Default)]
+pub struct MsgTableRef<'a>(&'a [u8], ParseOptions, usize);
+
+impl<'a> MsgTableRef<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data, ParseOptions::default(), 0)
+    }
+
+    /**
+     * BlobMsgs are decoded with `options` while walking, e.g. to tell INT8 from BOOL
+     */
+    pub fn with_options(data: &'a [u8], options: ParseOptions) -> Self {
+        Self(data, options, 0)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.len() < BlobTag::SIZE
+    }
+
+    /**
+     * walk the BlobMsgs, an `Err` is yielded once if the bytes are broken, then the iteration stops
+     */
+    pub fn iter(&self) -> MsgTableRefIter<'a> {
+        MsgTableRefIter {
+            data: self.0,
+            offset: 0,
+            options: self.1,
+        }
+    }
+
+    /**
+     * find the first BlobMsg named `name`, broken data is treated as not found
+     */
+    pub fn get(&self, name: &str) -> Option<BlobMsgPayloadRef<'a>> {
+        self.iter()
+            .map_while(Result::ok)
+            .find(|blobmsg| blobmsg.name == name)
+            .map(|blobmsg| blobmsg.data)
+    }
+
+    pub fn to_msg_table(&self) -> Result<MsgTable, UbusError> {
+        (*self).try_into()
+    }
+
+    /**
+     * same as `to_msg_table()`, this table counts as one level of `options.max_depth`
+     *
+     * `options` replaces the ones this view was created with
+     */
+    pub fn to_msg_table_with_options(&self, options: ParseOptions) -> Result<MsgTable, UbusError> {
+        let options = options.nested()?;
+        let mut iter = MsgTableRef(self.0, options, self.2).iter();
+        let mut blobmsgs = Vec::new();
+        loop {
+            let offset = iter.offset;
+            let Some(blobmsg) = iter.next() else {
+                break;
+            };
+            let blobmsg = blobmsg?;
+            /* errors of nested tables are located from their payload, shift them to the start of this table */
+            let (payload_offset, blob_type) = match blobmsg.data {
+                BlobMsgPayloadRef::Array(nested) => (nested.2, BlobMsgType::ARRAY),
+                BlobMsgPayloadRef::Table(nested) => (nested.2, BlobMsgType::TABLE),
+                _ => (0, BlobMsgType::UNSPEC),
+            };
+            blobmsgs.push(
+                blobmsg
+                    .to_blob_msg_with_options(options)
+                    .map_err(|e| e.at(offset + payload_offset, blob_type.value()))?,
+            );
+        }
+        Ok(MsgTable(blobmsgs))
+    }
+}
+
+impl<'a> IntoIterator for MsgTableRef<'a> {
+    type Item = Result<BlobMsgRef<'a>, UbusError>;
+    type IntoIter = MsgTableRefIter<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> TryFrom<MsgTableRef<'a>> for MsgTable {
+    type Error = UbusError;
+    fn try_from(table: MsgTableRef<'a>) -> Result<Self, Self::Error> {
+        table.to_msg_table_with_options(table.1)
+    }
+}
+
+impl<'a> core::fmt::Debug for MsgTableRef<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct MsgTableRefIter<'a> {
+    data: &'a [u8],
+    /* where `data` starts in the table, to locate errors */
+    offset: usize,
+    options: ParseOptions,
+}
+
+impl<'a> Iterator for MsgTableRefIter<'a> {
+    type Item = Result<BlobMsgRef<'a>, UbusError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < BlobTag::SIZE {
+            return None;
+        }
+        let tag = BlobTag::from_bytes(&self.data[..BlobTag::SIZE].try_into().unwrap());
+        let blobmsg = BlobMsgRef::from_bytes_with_options(self.data, self.options);
+        /* the last blob may come without padding */
+        let next_idx = match blobmsg {
+            Ok(_) => tag.next_tag().min(self.data.len()),
+            Err(_) => self.data.len(),
+        };
+        let offset = self.offset;
+        self.data = &self.data[next_idx..];
+        self.offset += next_idx;
+        Some(blobmsg.map_err(|e| e.at(offset, tag.blob_type())))
+    }
+}
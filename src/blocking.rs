@@ -0,0 +1,422 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::string::ToString;
+use std::time::{Duration, Instant};
+use std::vec;
+use std::vec::Vec;
+
+use crate::{
+    HexU32, MsgTable, NumberEncoding, ParseOptions, Protocol, ProtocolEvent, UBUS_DEFAULT_SOCKET,
+    UBUS_SYSTEM_OBJECT_EVENT, UbusBlob, UbusError, UbusInvoke, UbusMsgStatus, UbusObject, msgtable,
+    permission_denied_of,
+};
+
+/**
+ * a blocking ubus client over `std::os::unix::net::UnixStream`, for tools which don't run a tokio runtime
+ *
+ * it drives the same `Protocol` as the async `Connection`, every call writes the request and reads until
+ * its reply comes. INVOKEs from ubusd (calls of added objects, notifications of subscriptions, and events)
+ * received meanwhile are queued, and taken by `Connection::next_invoke()`
+ *
+ * requests are sent one by one, so they need `&mut self`. fds can't be passed with a std `UnixStream`,
+ * use the async `Connection` for `invoke_with_fd()`
+ *
+ * ```no_run
+ * use ubus::{MsgTable, UbusMsgStatus, blocking::Connection};
+ * let mut conn = Connection::connect_ubusd().unwrap();
+ * let board = conn.call("system", "board", MsgTable::new()).unwrap();
+ * println!("{:?}", board);
+ *
+ * conn.listen("network.*").unwrap();
+ * while let Some(event) = conn.next_invoke(None).unwrap() {
+ *     println!("{}: {:?}", event.method, event.args);
+ *     if !event.no_reply {
+ *         conn.reply_status(&event, UbusMsgStatus::OK).unwrap();
+ *     }
+ * }
+ * ```
+ */
+pub struct Connection {
+    stream: UnixStream,
+    protocol: Protocol,
+    client_id: HexU32,
+    /**
+     * how long to wait for a reply, 3s by default like the async `Connection`
+     */
+    timeout: Duration,
+    number_encoding: NumberEncoding,
+    /**
+     * replies of the request being waited, by sequence
+     */
+    replies: HashMap<u16, Result<Vec<Vec<UbusBlob>>, UbusError>>,
+    /**
+     * INVOKEs received while waiting for replies, taken by `next_invoke()`
+     */
+    invokes: VecDeque<UbusInvoke>,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    /**
+     * connect to ubusd at `path`, a path starting with `@` is an abstract socket
+     */
+    pub fn connect(path: &Path) -> Result<Self, UbusError> {
+        Self::new(Self::connect_unix_stream(path)?)
+    }
+
+    /**
+     * connect to the system ubusd, or what `UBUS_SOCKET` says, see the async `Connection::connect_ubusd()`
+     *
+     * TCP is not supported here
+     */
+    pub fn connect_ubusd() -> Result<Self, UbusError> {
+        match std::env::var("UBUS_SOCKET") {
+            Ok(socket) if socket.starts_with("tcp://") => Err(UbusError::IO(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "blocking connection over TCP",
+            ))),
+            Ok(socket) if !socket.is_empty() => Self::connect(Path::new(&socket)),
+            _ => Self::connect(Path::new(UBUS_DEFAULT_SOCKET)),
+        }
+    }
+
+    pub fn new(stream: UnixStream) -> Result<Self, UbusError> {
+        Self::new_with_options(stream, ParseOptions::default())
+    }
+
+    /**
+     * wait for the HELLO from ubusd, so `client_id()` is known once connected
+     */
+    pub fn new_with_options(stream: UnixStream, options: ParseOptions) -> Result<Self, UbusError> {
+        let mut conn = Self {
+            stream,
+            protocol: Protocol::with_options(options),
+            client_id: 0.into(),
+            timeout: Duration::from_millis(3000),
+            number_encoding: NumberEncoding::default(),
+            replies: HashMap::new(),
+            invokes: VecDeque::new(),
+            buffer: vec![0; 64 * 1024],
+        };
+        let deadline = Instant::now() + conn.timeout;
+        conn.client_id = loop {
+            if let Some(client_id) = conn.protocol.client_id() {
+                break client_id;
+            }
+            conn.receive(Some(deadline))?;
+        };
+        Ok(conn)
+    }
+
+    pub fn client_id(&self) -> HexU32 {
+        self.client_id
+    }
+
+    /**
+     * how many requests are still waiting for their reply, none once a call returned
+     */
+    pub fn pending_count(&self) -> usize {
+        self.protocol.pending_count()
+    }
+
+    /**
+     * how long a request waits for its reply before `UbusError::ReplyTimeout`
+     */
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /**
+     * how integers of JSON args given to `call_json()`/`invoke_json()` are encoded,
+     * a `MsgTable` is sent as it is, see the async `Connection::set_number_encoding()`
+     */
+    pub fn set_number_encoding(&mut self, numbers: NumberEncoding) {
+        self.number_encoding = numbers;
+    }
+
+    pub fn ping(&mut self) -> Result<Duration, UbusError> {
+        let start = Instant::now();
+        self.request(Protocol::ping)?;
+        Ok(start.elapsed())
+    }
+
+    /**
+     * call server with path + method + args, same as `.lookup_id()` + `.invoke()`
+     */
+    pub fn call(
+        &mut self,
+        server_obj_path: &str,
+        method: &str,
+        req_args: MsgTable,
+    ) -> Result<MsgTable, UbusError> {
+        let server_obj_id = self.lookup_id(server_obj_path)?;
+        self.invoke(server_obj_id, method, req_args)
+            .map_err(|e| match e {
+                /* path is more readable than id */
+                UbusError::PermissionDenied { method, .. } => UbusError::PermissionDenied {
+                    object: server_obj_path.to_string(),
+                    method,
+                },
+                e => e,
+            })
+    }
+
+    /**
+     * same as `.call()`, but args are JSON, integers in it are encoded as `set_number_encoding()` says
+     */
+    pub fn call_json(
+        &mut self,
+        server_obj_path: &str,
+        method: &str,
+        req_args: &str,
+    ) -> Result<MsgTable, UbusError> {
+        let req_args = MsgTable::from_json(req_args, self.number_encoding)?;
+        self.call(server_obj_path, method, req_args)
+    }
+
+    pub fn invoke(
+        &mut self,
+        server_obj_id: HexU32,
+        method: &str,
+        req_args: MsgTable,
+    ) -> Result<MsgTable, UbusError> {
+        let ubus_blobs_list = self
+            .request(|protocol| protocol.invoke(server_obj_id, method, req_args))
+            .map_err(|e| permission_denied_of(e, server_obj_id, method))?;
+        Protocol::data_of(ubus_blobs_list).ok_or(UbusError::InvalidData("response is empty"))
+    }
+
+    /**
+     * same as `.invoke()`, but args are JSON, see `call_json()`
+     */
+    pub fn invoke_json(
+        &mut self,
+        server_obj_id: HexU32,
+        method: &str,
+        req_args: &str,
+    ) -> Result<MsgTable, UbusError> {
+        let req_args = MsgTable::from_json(req_args, self.number_encoding)?;
+        self.invoke(server_obj_id, method, req_args)
+    }
+
+    pub fn lookup_id(&mut self, obj_path: &str) -> Result<HexU32, UbusError> {
+        Ok(self
+            .lookup(obj_path)?
+            .first()
+            .ok_or(UbusError::InvalidPath(obj_path.to_string()))?
+            .id)
+    }
+
+    pub fn lookup(&mut self, obj_path: &str) -> Result<Vec<UbusObject>, UbusError> {
+        let ubus_blobs_list = self.request(|protocol| protocol.lookup(obj_path))?;
+        Ok(ubus_blobs_list
+            .into_iter()
+            .map(UbusObject::from_blobs)
+            .collect())
+    }
+
+    /**
+     * add an object with methods, calls of them come from `next_invoke()`,
+     * an empty path adds an anonymous object, e.g. to `subscribe()` with
+     */
+    pub fn add_object(&mut self, obj_path: &str, methods: &[&str]) -> Result<HexU32, UbusError> {
+        let ubus_blobs_list =
+            self.request(|protocol| protocol.add_object(obj_path, methods.iter().copied()))?;
+        Ok(UbusObject::from_blobs(ubus_blobs_list.into_iter().flatten().collect()).id)
+    }
+
+    pub fn notify(
+        &mut self,
+        server_obj_id: HexU32,
+        method: &str,
+        data: MsgTable,
+    ) -> Result<(), UbusError> {
+        self.request(|protocol| protocol.notify(server_obj_id, method, data))
+            .map(drop)
+    }
+
+    /**
+     * notifications of `server_obj_id` come from `next_invoke()` as INVOKEs of `listener_obj_id`
+     */
+    pub fn subscribe(
+        &mut self,
+        listener_obj_id: HexU32,
+        server_obj_id: HexU32,
+    ) -> Result<(), UbusError> {
+        self.request(|protocol| protocol.subscribe(listener_obj_id, server_obj_id))
+            .map(drop)
+    }
+
+    /**
+     * listen to events matching `pattern` (e.g. `network.*`), like `ubus_register_event_handler()`
+     *
+     * events come from `next_invoke()` with the event name as `method` and the event data as `args`,
+     * the returned id is the `obj_id` of them, they come with `no_reply` set
+     */
+    pub fn listen(&mut self, pattern: &str) -> Result<HexU32, UbusError> {
+        let listener_obj_id = self.add_object("", &[])?;
+        /* ubusd replies no data to it, so not `invoke()` */
+        self.request(|protocol| {
+            protocol.invoke(
+                UBUS_SYSTEM_OBJECT_EVENT.into(),
+                "register",
+                msgtable! { "object": u32::from(listener_obj_id), "pattern": pattern },
+            )
+        })?;
+        Ok(listener_obj_id)
+    }
+
+    /**
+     * send an event to listeners, like `ubus_send_event()`
+     */
+    pub fn send_event(&mut self, id: &str, data: MsgTable) -> Result<(), UbusError> {
+        self.request(|protocol| {
+            protocol.invoke(
+                UBUS_SYSTEM_OBJECT_EVENT.into(),
+                "send",
+                msgtable! { "id": id, "data": data },
+            )
+        })
+        .map(drop)
+    }
+
+    /**
+     * the next INVOKE to objects of this connection, `None` if nothing comes within `timeout`,
+     * `None` timeout waits forever
+     *
+     * finish it with `reply()` or `reply_status()`, or the caller waits until its timeout,
+     * except when `no_reply` is set, e.g. events and notifications, nothing should be replied then
+     */
+    pub fn next_invoke(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<UbusInvoke>, UbusError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(invoke) = self.invokes.pop_front() {
+                return Ok(Some(invoke));
+            }
+            match self.receive(deadline) {
+                Err(UbusError::ReplyTimeout()) => return Ok(None),
+                result => result?,
+            }
+        }
+    }
+
+    /**
+     * reply data and `UbusMsgStatus::OK` to an INVOKE
+     */
+    pub fn reply(&mut self, invoke: &UbusInvoke, data: MsgTable) -> Result<(), UbusError> {
+        self.protocol.reply_data(invoke, data)?;
+        self.reply_status(invoke, UbusMsgStatus::OK)
+    }
+
+    pub fn reply_status(
+        &mut self,
+        invoke: &UbusInvoke,
+        status: UbusMsgStatus,
+    ) -> Result<(), UbusError> {
+        self.protocol.reply_status(invoke, status)?;
+        self.transmit()
+    }
+}
+
+/**
+ * internally used
+ */
+impl Connection {
+    fn connect_unix_stream(path: &Path) -> Result<UnixStream, UbusError> {
+        use std::os::unix::ffi::OsStrExt;
+
+        match path.as_os_str().as_bytes().strip_prefix(b"@") {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+
+                let addr = SocketAddr::from_abstract_name(name).map_err(UbusError::IO)?;
+                UnixStream::connect_addr(&addr).map_err(UbusError::IO)
+            }
+            _ => UnixStream::connect(path).map_err(UbusError::IO),
+        }
+    }
+
+    /**
+     * send a request made by `f`, and read until its reply comes
+     */
+    fn request(
+        &mut self,
+        f: impl FnOnce(&mut Protocol) -> Result<u16, UbusError>,
+    ) -> Result<Vec<Vec<UbusBlob>>, UbusError> {
+        let sequence = f(&mut self.protocol)?;
+        if let Err(e) = self.transmit() {
+            self.protocol.cancel(sequence);
+            return Err(e);
+        }
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(result) = self.replies.remove(&sequence) {
+                return result;
+            }
+            if let Err(e) = self.receive(Some(deadline)) {
+                self.protocol.cancel(sequence);
+                return Err(e);
+            }
+        }
+    }
+
+    fn transmit(&mut self) -> Result<(), UbusError> {
+        while let Some(message) = self.protocol.poll_transmit() {
+            self.stream.write_all(&message).map_err(UbusError::IO)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * read once from the stream and dispatch what `Protocol` makes of it
+     */
+    fn receive(&mut self, deadline: Option<Instant>) -> Result<(), UbusError> {
+        let timeout = match deadline {
+            Some(deadline) => Some(
+                deadline
+                    .checked_duration_since(Instant::now())
+                    .filter(|timeout| !timeout.is_zero())
+                    .ok_or(UbusError::ReplyTimeout())?,
+            ),
+            None => None,
+        };
+        self.stream
+            .set_read_timeout(timeout)
+            .map_err(UbusError::IO)?;
+        let len = match self.stream.read(&mut self.buffer) {
+            Ok(0) => return Err(UbusError::UnexpectChannelClosed()),
+            Ok(len) => len,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(UbusError::ReplyTimeout());
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(UbusError::IO(e)),
+        };
+        self.protocol.receive(&self.buffer[..len])?;
+        while let Some(event) = self.protocol.poll_event() {
+            match event {
+                ProtocolEvent::Hello(_) => {}
+                ProtocolEvent::Reply { sequence, result } => {
+                    self.replies.insert(sequence, result);
+                }
+                ProtocolEvent::Invoke(invoke) => self.invokes.push_back(invoke),
+                ProtocolEvent::Notify { obj_id, active } => {
+                    log::trace!("obj {:?} has subscribers: {:?}", obj_id, active)
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -3,19 +3,31 @@ use crate::{
     *,
 };
 
-use core::{ops::Not, sync::atomic::AtomicU16, time::Duration};
-use std::{collections::HashMap, format, string::ToString, sync::Arc, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use std::{
+    boxed::Box,
+    collections::HashMap,
+    format,
+    os::fd::OwnedFd,
+    string::ToString,
+    sync::{Arc, Mutex},
+    vec::Vec,
+};
 extern crate alloc;
 use alloc::string::String;
-use std::vec;
-use storage_endian::BigEndian;
 use tokio::{
-    sync::{RwLock, mpsc},
+    sync::{RwLock, mpsc, oneshot},
     task::JoinSet,
-    time::timeout,
+    time::{Instant, timeout},
 };
 use ubuserror::*;
 
+/* how long a request waits for its STATUS */
+const REPLY_TIMEOUT: Duration = Duration::from_millis(3000);
+
 #[derive(Copy, Clone)]
 pub struct ObjectResult<'a> {
     pub path: &'a str,
@@ -34,27 +46,38 @@ pub struct SignatureResult<'a> {
     pub args: HashMap<String, BlobMsgType>,
 }
 
+/**
+ * an encoded message and the fd passed along with it, in ubus only INVOKE and STATUS carry fds
+ */
+type TransmitWithFd = (Vec<u8>, Option<OwnedFd>);
+
+/**
+ * what a request waits for, its `ProtocolEvent::Reply` and the fd passed along with the STATUS
+ */
+type ReplyWithFd = (Result<Vec<Vec<UbusBlob>>, UbusError>, Option<OwnedFd>);
+
+/**
+ * health of the connection, only a keepalive task (see `Connection::spawn_keepalive()`) marks it failed
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Alive,
+    /* ubusd stopped answering PINGs, every new request fails with `UbusError::ConnectionFailed` */
+    Failed,
+}
+
 #[derive()]
 pub struct Connection {
     // io: T,
-    /*
-     * each connection has a client_id generated by ubus
+    /**
+     * each connection has a client_id generated by ubus, told by the HELLO on connect
      * in server mode,
      *      this is not used, instead server_obj_id is used
      * in client mode,
      *      this is what server see as a message.header.peer,
      *      but we don't need to set this, (we manually set it to server_obj_id), ubusd will set for us
      */
-    // peer: u32,
-    /*
-     * in client mode,
-     *      each request need a sequence to identify from other requests
-     *      different client_obj (i.e. new connection) has different client_obj_id so it's okay to use same sequence
-     *
-     * // wrapped in arc so reference doesn't need to be mut
-     * use AtomicU16 instead
-     */
-    sequence: AtomicU16,
+    client_id: HexU32,
     /*
      * not used, we use a heap allocated Vec to store received data
      */
@@ -64,52 +87,95 @@ pub struct Connection {
      */
     server_objs: Arc<RwLock<HashMap<u32, UbusServerObject>>>,
     /**
-     * (need redesign) if a UbusMsg is received from socket, the MassageManager will use the (peer, objid, seq) to identify which received to send
-     * seq
-     */
-    reply_receivers_tx: Arc<RwLock<HashMap<u16, mpsc::Sender<UbusMsg>>>>,
-    /**
-     * should each server has its own channel?
+     * everything needed to send a request and wait for its reply
      */
-    // invoke_receiver_tx: mpsc::Sender<UbusMsg>,
-    // invoke_receiver_rx: mpsc::Receiver<UbusMsg>,
+    requester: Requester,
     /**
-     * send message to MessageManager and let it send to wire
+     * how integers of JSON args are encoded, see `set_number_encoding()`
      */
-    message_sender_tx: mpsc::Sender<UbusMsg>,
+    number_encoding: NumberEncoding,
     /**
      * run necessary loops in background, spawned in new(), aborted when dropped
      *  - invoke_handler    :   handle client's INVOKEs and call callbacks
-     *  - message_manager   :   communicate with io (e.g. ubusd via UnixStream), dispatch messages through channels
+     *  - message_manager   :   communicate with io (e.g. ubusd via UnixStream), feed `Protocol` and dispatch its events
+     *  - keepalive         :   (optional) PING ubusd periodically, see `spawn_keepalive()`
      */
     communication_loops: JoinSet<()>,
 }
 
+/**
+ * the tokio driver of `Protocol`, split out of `Connection` so background tasks (e.g. keepalive) can send requests too
+ *
+ * everything inside is shared, so clone is cheap
+ */
+#[derive(Clone)]
+struct Requester {
+    /**
+     * sequences, pending requests, and encoding, all protocol state lives here
+     *
+     * it's locked only shortly and never across an await, so a sync Mutex is fine
+     */
+    protocol: Arc<Mutex<Protocol>>,
+    /**
+     * requests waiting for their reply, by sequence, the message_receiver wakes them up
+     */
+    reply_waiters: Arc<Mutex<HashMap<u16, oneshot::Sender<ReplyWithFd>>>>,
+    /**
+     * send message to MessageManager and let it send to wire
+     */
+    message_sender_tx: mpsc::Sender<TransmitWithFd>,
+    /**
+     * requests of `invoke_raw()` by sequence, the message_receiver hands their DATA to them in place
+     */
+    raw_handlers: Arc<Mutex<HashMap<u16, RawReplyHandler>>>,
+    /**
+     * set by keepalive once ubusd stops answering
+     */
+    failed: Arc<AtomicBool>,
+}
+
+/* looks at the reply data while it's still in the receive buffer */
+type RawReplyHandler = Box<dyn FnOnce(MsgTableRef) + Send>;
+
 impl Connection {
     /**
      * Create a new ubus connection from an existing IO
      *
+     * ubusd says HELLO right after connect, this waits for it (up to 3s) to learn our client_id
      */
-    pub async fn new<R: AsyncIoReader, W: AsyncIoWriter>(
+    pub async fn new<R: AsyncIoReader, W: AsyncIoWriter>(io: (R, W)) -> Result<Self, UbusError> {
+        Self::new_with_options(io, ParseOptions::default()).await
+    }
+
+    /**
+     * same as `new()`, incoming messages are decoded with `options`, e.g. to lower the size limit
+     * on small devices, messages beyond the limits are dropped with a warning
+     */
+    pub async fn new_with_options<R: AsyncIoReader, W: AsyncIoWriter>(
         (io_reader, io_writer): (R, W),
+        options: ParseOptions,
     ) -> Result<Self, UbusError> {
         let (invoke_receiver_tx, invoke_receiver_rx) = mpsc::channel(8);
         let (message_sender_tx, message_sender_rx) = mpsc::channel(8);
+        let (hello_tx, hello_rx) = oneshot::channel();
 
         let mut conn = Self {
-            // peer: 0,
-            sequence: 0.into(),
+            client_id: 0.into(),
             // buffer: [0u8; 64 * 1024],
             server_objs: Arc::new(RwLock::new(HashMap::new())),
-            reply_receivers_tx: Arc::new(RwLock::new(HashMap::new())),
-            // invoke_receiver_tx: invoke_receiver_tx.clone(),
-            message_sender_tx: message_sender_tx.clone(),
+            requester: Requester {
+                protocol: Arc::new(Mutex::new(Protocol::with_options(options))),
+                reply_waiters: Arc::new(Mutex::new(HashMap::new())),
+                raw_handlers: Arc::new(Mutex::new(HashMap::new())),
+                message_sender_tx,
+                failed: Arc::new(false.into()),
+            },
+            number_encoding: NumberEncoding::default(),
             // invoke_handler: None,
             // message_manager: None,
             communication_loops: JoinSet::new(),
         };
 
-        let reply_receivers_tx = conn.reply_receivers_tx.clone();
         /*
          * spawn and move the io to it makes it run forever, independent of how long the Connection struct lives
          */
@@ -121,31 +187,41 @@ impl Connection {
         conn.communication_loops.spawn(Self::run_invoke_handler(
             conn.server_objs.clone(), /* clone the Arc */
             invoke_receiver_rx,
-            message_sender_tx,
+            conn.requester.clone(),
         ));
         conn.communication_loops.spawn(Self::run_message_receiver(
             io_reader,
-            reply_receivers_tx,
+            conn.requester.clone(),
             invoke_receiver_tx,
+            hello_tx,
+            options,
         ));
         conn.communication_loops
             .spawn(Self::run_message_sender(io_writer, message_sender_rx));
 
-        // ubus server should say hello on connect
-        // let message = conn.next_message().await?;
-
-        // // Verify the header is what we expect
-        // valid_data!(
-        //     message.header.cmd_type == UbusCmdType::HELLO,
-        //     "Expected hello"
-        // );
-
-        // Record our peer id
-        // conn.peer = message.header.peer.into();
+        /* ubus server should say hello on connect, record our client_id */
+        conn.client_id = match timeout(Duration::from_millis(3000), hello_rx).await {
+            Ok(Ok(client_id)) => client_id,
+            Ok(Err(_)) => {
+                log::warn!("message_receiver crashed before got a HELLO");
+                return Err(UbusError::UnexpectChannelClosed());
+            }
+            Err(_) => {
+                log::warn!("waiting for long time but ubusd doesn't say HELLO");
+                return Err(UbusError::ReplyTimeout());
+            }
+        };
 
         Ok(conn)
     }
 
+    /**
+     * the client_id ubusd assigned to this connection, same as the `peer` shown by `ubus monitor`
+     */
+    pub fn client_id(&self) -> HexU32 {
+        self.client_id
+    }
+
     /**
      * block forever.
      * this doesn't need to be call to run, loops are running in background when `new()`
@@ -169,13 +245,71 @@ impl Connection {
     //     // self.
     // }
     pub async fn send_message(&self, message: UbusMsg) -> Result<(), UbusError> {
-        // self.io.put(&Into::<Vec<u8>>::into(message))
-        // self.io.put(&message.to_bytes()).await
-        self.message_sender_tx
-            .send(message)
-            .await
-            .inspect_err(|_| log::warn!("failed to send because message_sender crashed!"))
-            .map_err(|_| UbusError::UnexpectChannelClosed())
+        self.requester.send_message(message).await
+    }
+
+    /**
+     * send a PING to ubusd and measure how long until it replies
+     */
+    pub async fn ping(&self) -> Result<Duration, UbusError> {
+        let start = Instant::now();
+        self.requester.request(Protocol::ping).await?;
+        Ok(start.elapsed())
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.requester.state()
+    }
+
+    /**
+     * how integers of JSON args given to `call_json()`/`invoke_json()` are encoded,
+     * e.g. `NumberEncoding::Int32OrInt64` for C servers whose policies expect what `blobmsg_add_json()` gives
+     *
+     * a `MsgTable` is sent as it is, integers with an explicit width (e.g. `msgtable!{ "x": 10i16 }`)
+     * are never changed, see `MsgTable::widen_numbers()` to widen them on purpose
+     */
+    pub fn set_number_encoding(&mut self, numbers: NumberEncoding) {
+        self.number_encoding = numbers;
+    }
+
+    /**
+     * spawn a background task which PINGs ubusd every `interval`
+     *
+     * after `max_missed` PINGs in a row got no reply, the connection is marked as `ConnectionState::Failed`,
+     * the task stops, and every following request returns `UbusError::ConnectionFailed`
+     *
+     * each PING waits for its reply up to `interval`, but no longer than the 3s of other requests,
+     * so a dead ubusd is found in about `max_missed * interval`
+     */
+    pub fn spawn_keepalive(&mut self, interval: Duration, max_missed: u32) {
+        let requester = self.requester.clone();
+        let ping_timeout = interval.min(REPLY_TIMEOUT);
+        self.communication_loops.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut missed = 0;
+            loop {
+                ticker.tick().await;
+                let start = Instant::now();
+                match requester
+                    .request_inner(Protocol::ping, None, None, ping_timeout)
+                    .await
+                {
+                    Ok(_) => {
+                        log::trace!("keepalive: ubusd replied PING in {:?}", start.elapsed());
+                        missed = 0;
+                    }
+                    Err(e) => {
+                        missed += 1;
+                        log::warn!("keepalive: PING failed ({}/{}): {}", missed, max_missed, e);
+                        if missed >= max_missed {
+                            log::error!("keepalive: ubusd stops answering, mark connection failed");
+                            requester.failed.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    }
+                }
+            }
+        });
     }
 
     /**
@@ -190,7 +324,29 @@ impl Connection {
         req_args: MsgTable,
     ) -> Result<MsgTable, UbusError> {
         let server_obj_id = self.lookup_id(server_obj_path).await?;
-        self.invoke(server_obj_id, method, req_args).await
+        self.invoke(server_obj_id, method, req_args)
+            .await
+            .map_err(|e| match e {
+                /* path is more readable than id */
+                UbusError::PermissionDenied { method, .. } => UbusError::PermissionDenied {
+                    object: server_obj_path.to_string(),
+                    method,
+                },
+                e => e,
+            })
+    }
+
+    /**
+     * same as `.call()`, but args are JSON, integers in it are encoded as `set_number_encoding()` says
+     */
+    pub async fn call_json(
+        &self,
+        server_obj_path: &str,
+        method: &str,
+        req_args: &str,
+    ) -> Result<MsgTable, UbusError> {
+        let req_args = MsgTable::from_json(req_args, self.number_encoding)?;
+        self.call(server_obj_path, method, req_args).await
     }
 
     /**
@@ -205,36 +361,94 @@ impl Connection {
         method: &str,
         req_args: MsgTable,
     ) -> Result<MsgTable, UbusError> {
-        match self
-            .send_message_and_handle_reply(
-                UbusCmdType::INVOKE,
-                server_obj_id,
-                vec![
-                    UbusBlob::ObjId(server_obj_id),
-                    UbusBlob::Method(method.to_string()),
-                    UbusBlob::Data(req_args),
-                ],
+        /* Normally we will get a UbusCmdType::DATA then a UbusCmdType::STATUS */
+        let ubus_blobs_list = self
+            .requester
+            .request(|protocol| protocol.invoke(server_obj_id, method, req_args))
+            .await
+            .map_err(|e| permission_denied_of(e, server_obj_id, method))?;
+        Protocol::data_of(ubus_blobs_list).ok_or(UbusError::InvalidData("response is empty"))
+    }
+
+    /**
+     * same as `.invoke()`, but args are JSON, see `call_json()`
+     */
+    pub async fn invoke_json(
+        &self,
+        server_obj_id: HexU32,
+        method: &str,
+        req_args: &str,
+    ) -> Result<MsgTable, UbusError> {
+        let req_args = MsgTable::from_json(req_args, self.number_encoding)?;
+        self.invoke(server_obj_id, method, req_args).await
+    }
+
+    /**
+     * same as `.invoke()`, but pass an fd to the server along with the request,
+     * and get the fd the server attached to its reply (e.g. a pipe streaming logs)
+     *
+     * a reply may only contain an fd, so missing data is an empty MsgTable instead of an error here
+     */
+    pub async fn invoke_with_fd(
+        &self,
+        server_obj_id: HexU32,
+        method: &str,
+        req_args: MsgTable,
+        fd: Option<OwnedFd>,
+    ) -> Result<(MsgTable, Option<OwnedFd>), UbusError> {
+        let (ubus_blobs_list, reply_fd) = self
+            .requester
+            .request_with_fd(
+                |protocol| protocol.invoke(server_obj_id, method, req_args),
+                fd,
             )
             .await
-        {
-            /* Normally we will get a UbusCmdType::DATA then a UbusCmdType::STATUS */
-            Ok(ubus_blobs_list) => {
-                let maybe_reply_args =
-                    ubus_blobs_list
-                        .into_iter()
-                        .flatten()
-                        .find_map(|ubus_blob| match ubus_blob {
-                            UbusBlob::Data(d) => Some(d),
-                            _ => None,
-                        });
-                if let Some(reply_args) = maybe_reply_args {
-                    Ok(reply_args)
-                } else {
-                    Err(UbusError::InvalidData("response is empty"))
-                }
-            }
-            Err(e) => Err(e),
-        }
+            .map_err(|e| permission_denied_of(e, server_obj_id, method))?;
+        Ok((
+            Protocol::data_of(ubus_blobs_list).unwrap_or_default(),
+            reply_fd,
+        ))
+    }
+
+    /**
+     * same as `.invoke()`, but `f` looks at the reply data in place, instead of getting it parsed into a `MsgTable`,
+     * e.g. to pick a few fields out of a large `network.device status`
+     *
+     * `f` runs in the task receiving messages, nothing else is received until it returns, so keep it short
+     */
+    pub async fn invoke_raw<T: Send + 'static>(
+        &self,
+        server_obj_id: HexU32,
+        method: &str,
+        req_args: MsgTable,
+        f: impl FnOnce(MsgTableRef) -> T + Send + 'static,
+    ) -> Result<T, UbusError> {
+        let (result_tx, mut result_rx) = oneshot::channel();
+        self.requester
+            .request_raw(
+                |protocol| protocol.invoke(server_obj_id, method, req_args),
+                Box::new(move |data| {
+                    let _ = result_tx.send(f(data));
+                }),
+            )
+            .await
+            .map_err(|e| permission_denied_of(e, server_obj_id, method))?;
+        /* the DATA comes before the STATUS, so it's there if any */
+        result_rx
+            .try_recv()
+            .map_err(|_| UbusError::InvalidData("response is empty"))
+    }
+
+    /**
+     * ask ubusd for ACLs of all server objects added by this connection,
+     * so servers can decide what a user/group is allowed to do, see `UbusAclTable::lookup()`
+     *
+     * same as `ubus_register_acl()` in libubus, but without subscribing `UBUS_ACL_SEQUENCE_EVENT`
+     */
+    pub async fn query_acl(&self) -> Result<UbusAclTable, UbusError> {
+        self.invoke(UBUS_SYSTEM_OBJECT_ACL.into(), "query", MsgTable::new())
+            .await?
+            .try_into()
     }
 
     // pub fn lookup_object_json<'a>(&'a mut self, obj_path: &'a str) -> Result<String, UbusError> {
@@ -253,46 +467,15 @@ impl Connection {
     }
 
     pub async fn lookup(&self, obj_path: &str) -> Result<Vec<UbusObject>, UbusError> {
-        let reply = self
-            .send_message_and_handle_reply(
-                UbusCmdType::LOOKUP,
-                0.into(),
-                obj_path
-                    .is_empty()
-                    .not()
-                    .then(|| UbusBlob::ObjPath(obj_path.to_string()))
-                    .into_iter()
-                    .collect(),
-            )
-            .await;
-        match reply {
-            Ok(ubus_blobs_list) => {
-                let found_objs = ubus_blobs_list
-                    .into_iter()
-                    .map(|ubus_blobs| {
-                        /*
-                         * here the `obj` is inserted with some reference from `message`, then if we try to return it, rust ensure `message` should live longer than `obj`
-                         * i check the code, message is a lot of slice to a global buffer in Connection, each time next_message() got called, the global buffer got overriden
-                         */
-                        let mut obj = UbusObject::default();
-                        for ubus_blob in ubus_blobs {
-                            match ubus_blob {
-                                UbusBlob::ObjPath(path) => obj.path = path.to_string(),
-                                UbusBlob::ObjId(id) => obj.id = id,
-                                UbusBlob::ObjType(ty) => obj.objtype = ty,
-                                UbusBlob::Signature(nested) => {
-                                    obj.reported_signature = nested;
-                                }
-                                _ => {}
-                            }
-                        }
-                        obj
-                    })
-                    .collect();
-                Ok(found_objs)
-            }
-            Err(e) => Err(e),
-        }
+        /* a DATA for each object found */
+        let ubus_blobs_list = self
+            .requester
+            .request(|protocol| protocol.lookup(obj_path))
+            .await?;
+        Ok(ubus_blobs_list
+            .into_iter()
+            .map(UbusObject::from_blobs)
+            .collect())
     }
 
     /*
@@ -341,48 +524,24 @@ impl Connection {
         server_obj_builder: UbusServerObjectBuilder,
     ) -> Result<u32, UbusError> {
         // FIXME\: official ubus cli call stuck while data in monitor looks good <- fixed: replied seq should be same as requested
-        let mut new_server_obj = {
-            let reply = self
-                .send_message_and_handle_reply(
-                    UbusCmdType::ADD_OBJECT,
-                    0.into(),
-                    vec![
-                        UbusBlob::ObjPath(server_obj_builder.path),
-                        UbusBlob::Signature(
-                            server_obj_builder
-                                .methods
-                                .iter()
-                                .map(|(method, _)| BlobMsg {
-                                    name: method.to_string(),
-                                    data: BlobMsgPayload::Table(Vec::new()),
-                                })
-                                .collect::<Vec<BlobMsg>>()
-                                .into(),
-                        ),
-                    ],
+        /* Normally we will get a UbusCmdType::DATA then a UbusCmdType::STATUS */
+        let ubus_blobs_list = self
+            .requester
+            .request(|protocol| {
+                protocol.add_object(
+                    &server_obj_builder.path,
+                    server_obj_builder.methods.keys().map(String::as_str),
                 )
-                .await;
-            match reply {
-                Ok(ubus_blobs_list) => {
-                    /* Normally we will get a UbusCmdType::DATA then a UbusCmdType::STATUS */
-                    let mut new_server_obj: UbusServerObject = UbusServerObject::default();
-
-                    for ubus_blob in ubus_blobs_list.into_iter().flatten() {
-                        match ubus_blob {
-                            UbusBlob::ObjId(id) => new_server_obj.id = id,
-                            UbusBlob::ObjType(objtype) => new_server_obj.objtype = objtype,
-                            _ => todo!(),
-                        }
-                    }
-                    new_server_obj
-                }
-                Err(e) => return Err(e),
-            }
+            })
+            .await?;
+        let added = UbusObject::from_blobs(ubus_blobs_list.into_iter().flatten().collect());
+        let new_server_obj = UbusServerObject {
+            id: added.id,
+            objtype: added.objtype,
+            methods: server_obj_builder.methods,
+            ..Default::default()
         };
 
-        new_server_obj.methods = server_obj_builder.methods;
-        // new_server_obj.methods_async = server_obj_builder.methods_async;
-
         let new_server_obj_id = new_server_obj.id;
         self.server_objs
             .write()
@@ -426,24 +585,13 @@ impl Connection {
          * After restruct, client's status is ignored at all
          */
 
-        match self
-            .send_message_and_handle_reply(
-                UbusCmdType::NOTIFY,
-                server_obj_id.into(),
-                vec![
-                    UbusBlob::ObjId(server_obj_id.into()),
-                    UbusBlob::Method(method.into()),
-                    UbusBlob::Data(data),
-                ],
-            )
+        /*
+         * UbusBlob::Subscribers: only got this if no subscribers exist, with an empty MsgTable
+         */
+        self.requester
+            .request(|protocol| protocol.notify(server_obj_id.into(), method, data))
             .await
-        {
-            /*
-             * UbusBlob::Subscribers: only got this if no subscribers exist, with an empty MsgTable
-             */
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        }
+            .map(drop)
     }
 
     pub async fn subscribe(
@@ -451,20 +599,10 @@ impl Connection {
         listener_obj_id: HexU32,
         server_obj_id: HexU32,
     ) -> Result<(), UbusError> {
-        match self
-            .send_message_and_handle_reply(
-                UbusCmdType::SUBSCRIBE,
-                0.into(),
-                vec![
-                    UbusBlob::ObjId(listener_obj_id),
-                    UbusBlob::Target(server_obj_id),
-                ],
-            )
+        self.requester
+            .request(|protocol| protocol.subscribe(listener_obj_id, server_obj_id))
             .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        }
+            .map(drop)
     }
 }
 
@@ -472,28 +610,6 @@ impl Connection {
  * internally used
  */
 impl Connection {
-    /**
-     * sequence is used to identify session, only client need to generate it, server should reply with same sequence with request's
-     */
-    fn generate_new_request_sequence(&self) -> BigEndian<u16> {
-        // self.sequence = self.sequence.wrapping_add(1);
-        // BigEndian::<u16>::from(self.sequence)
-
-        /* also seems okay to use random number */
-        // BigEndian::<u16>::from(random::<u16>(..))
-
-        // let mut seq = self.sequence.lock().unwrap();
-        // *seq = seq.wrapping_add(1);
-        // BigEndian::<u16>::from(*seq)
-
-        /* seems fetch_add can wrapping */
-        let seq = self
-            .sequence
-            .fetch_add(1, core::sync::atomic::Ordering::Relaxed)
-            .wrapping_add(1);
-        BigEndian::<u16>::from(seq)
-    }
-
     /**
      * a dedicated task to handle all INVOKEs from client, call callbacks, and reply
      *
@@ -501,212 +617,80 @@ impl Connection {
      */
     async fn run_invoke_handler(
         server_objs: Arc<RwLock<HashMap<u32, UbusServerObject>>>,
-        mut invoke_receiver_rx: mpsc::Receiver<UbusMsg>,
-        message_sender_tx: mpsc::Sender<UbusMsg>,
+        mut invoke_receiver_rx: mpsc::Receiver<(UbusInvoke, Option<OwnedFd>)>,
+        requester: Requester,
     ) {
-        /* FIXME: it may be not ideal to abuse ? to throw error, each error should be handled correctly */
-        // let server_objs_map = HashMap::<u32, UbusServerObject>::from_iter(
-        //     server_objs.into_iter().map(|obj| (obj.id, obj)),
-        // );
-        /* Normally we will get a UbusCmdType::DATA then a UbusCmdType::STATUS */
-
         loop {
-            let message = invoke_receiver_rx
+            let (mut invoke, fd) = invoke_receiver_rx
                 .recv()
                 .await
                 .expect("failed to receive because message_receiver crashed!");
 
-            /* only INVOKE should reach here, is this syntax more readable than if let? */
-            let UbusCmdType::INVOKE = message.header.cmd_type else {
-                log::warn!(
-                    "invoke_handler got a non-INVOKE message {:?}, which is not expected",
-                    &message
-                );
-                continue;
-            };
-
-            /*
-             * server object normally won't got a status, instead, server will send back a status OK to terminate client
-             */
-            /*
-             * client's INVOKE contains:
-             *      - `message.header.peer`         : the client's obj_id, should be used as `message.header.peer` when reply
-             *      - `message.header.sequence`     : used to identify current session, should be used as `message.header.sequence` when reply
-             *      - `message.ubus_blobs.?.ObjId`  : current server obj_id, should be used as `message.ubus_blobs.?.ObjId` when reply.
-             *                                        this is same as the response from add_server
-             *      - `message.ubus_blobs.?.Method` : client want to call this method
-             *      - `message.ubus_blobs.?.Data`   : client requested with this json
-             */
-            // TODO: use Option
-            let Some((requested_server_obj_id, method_name, req_args)) = ({
-                let mut requested_server_obj_id = None;
-                let mut method_name = None;
-                let mut req_args = None;
-                for blob in message.ubus_blobs {
-                    // dbg!(&blob);
-                    match blob {
-                        UbusBlob::ObjId(id) => requested_server_obj_id = Some(id),
-                        UbusBlob::Method(method) => method_name = Some(method),
-                        UbusBlob::Data(msg_table) => req_args = Some(msg_table),
-                        _ => {}
-                    }
-                }
-                match (requested_server_obj_id, method_name, req_args) {
-                    (Some(requested_server_obj_id), Some(method_name), Some(req_args)) => {
-                        Some((requested_server_obj_id, method_name, req_args))
-                    }
-                    _ => None,
-                }
-            }) else {
-                log::warn!(
-                    "invoke_handler can't get obj_id, method_name, and req_args from INVOKE"
-                );
-                continue;
-            };
-
             enum FindMethodStatus {
                 Found(UbusMethod),
                 ObjectNotFound,
                 MethodNotFound,
-                // MethodDuplicated,
             }
-            // tokio::runtime::Runtime::new().unwrap().block_on(async {});
-
-            /* reply to client */
 
             /*
-             * try to get the method from the HashMap, clone the method Arc, then drop the sync::Mutex
-             * if we doesn't drop the Mutex,
+             * try to get the method from the HashMap, clone the method Arc, then drop the lock
+             * if we doesn't drop the lock,
              *      1. it can't be Send, compiler errors
              *      2. if the callback takes time, the callbacks HashMap is locked, and other callbacks can't get called
              */
-            let find_method_result = if let Some(server_obj) = server_objs
-                .read()
-                .await
-                .get(&requested_server_obj_id.into())
-            {
-                match server_obj.methods.get(&method_name) {
-                    // (Some(_), Some(_)) => FindMethodStatus::MethodDuplicated,
-                    // Some(UbusMethod::Sync(method)) => {
-                    //     FindMethodStatus::Found(UbusMethod::Sync(method.clone()))
-                    // }
-                    // Some(UbusMethod::Async(method_async)) => {
-                    //     FindMethodStatus::Found(UbusMethod::Async(method_async.clone()))
-                    // }
-                    Some(method) => FindMethodStatus::Found(method.clone()),
-                    None => FindMethodStatus::MethodNotFound,
-                }
-            } else {
-                FindMethodStatus::ObjectNotFound
-            };
+            let find_method_result =
+                if let Some(server_obj) = server_objs.read().await.get(&invoke.obj_id.into()) {
+                    match server_obj.methods.get(&invoke.method) {
+                        Some(method) => FindMethodStatus::Found(method.clone()),
+                        None => FindMethodStatus::MethodNotFound,
+                    }
+                } else {
+                    FindMethodStatus::ObjectNotFound
+                };
 
             /* use a dedicated task to run the callback */
-            // FIXME\: the unwrap may panic
-            let message_sender_tx = message_sender_tx.clone();
+            let requester = requester.clone();
             tokio::spawn(async move {
-                match find_method_result {
+                let (reply, status) = match find_method_result {
                     FindMethodStatus::Found(method) => {
-                        // tokio::spawn(async {});
-                        let reply_args = match method {
-                            UbusMethod::Sync(method) => method(req_args),
-                            UbusMethod::Async(method) => method(req_args).await,
+                        let req_args = core::mem::take(&mut invoke.args);
+                        let reply = match method {
+                            UbusMethod::Sync(method) => method(req_args).into(),
+                            UbusMethod::Async(method) => method(req_args).await.into(),
+                            UbusMethod::WithRequest(method) => method(UbusRequest {
+                                args: req_args,
+                                fd,
+                                user: invoke.user.take(),
+                                group: invoke.group.take(),
+                            }),
                         };
-                        /* here client_obj_id == server objid */
-                        message_sender_tx
-                            .send(UbusMsg {
-                                header: UbusMsgHeader {
-                                    version: UbusMsgVersion::CURRENT,
-                                    cmd_type: UbusCmdType::DATA,
-                                    sequence: message.header.sequence,
-                                    peer: message.header.peer,
-                                },
-                                ubus_blobs: vec![
-                                    UbusBlob::ObjId(requested_server_obj_id),
-                                    /* data is moved to enum, then moved to UbusMsg */
-                                    UbusBlob::Data(reply_args),
-                                ],
-                            })
-                            .await
-                            .inspect_err(|_| {
-                                log::warn!(
-                                    /* idk why but too long cause fmt broken */
-                                    "failed to send reply because the message_receiver is down"
-                                )
-                            })
-                            .ok();
-
-                        // dbg!(reply_args);
-
-                        // sleep(Duration::from_millis(400));
-
-                        message_sender_tx
-                            .send(UbusMsg {
-                                header: UbusMsgHeader {
-                                    version: UbusMsgVersion::CURRENT,
-                                    cmd_type: UbusCmdType::STATUS,
-                                    sequence: message.header.sequence,
-                                    peer: message.header.peer,
-                                },
-                                ubus_blobs: vec![
-                                    UbusBlob::ObjId(requested_server_obj_id),
-                                    UbusBlob::Status(UbusMsgStatus::OK),
-                                ],
-                            })
-                            .await
-                            .inspect_err(|_| {
-                                log::warn!(
-                                    "failed to send reply because the message receiver is down"
-                                )
-                            })
-                            .ok();
-                    }
-                    FindMethodStatus::MethodNotFound => {
-                        /* method not found */
-                        message_sender_tx
-                            .send(UbusMsg {
-                                header: UbusMsgHeader {
-                                    version: UbusMsgVersion::CURRENT,
-                                    cmd_type: UbusCmdType::STATUS,
-                                    sequence: message.header.sequence,
-                                    peer: message.header.peer,
-                                },
-                                ubus_blobs: vec![
-                                    UbusBlob::ObjId(requested_server_obj_id),
-                                    UbusBlob::Status(UbusMsgStatus::METHOD_NOT_FOUND),
-                                ],
-                            })
-                            .await
-                            .inspect_err(|_| {
-                                log::warn!(
-                                    "failed to send reply because the message_receiver is down"
-                                )
-                            })
-                            .ok();
-                    }
-                    FindMethodStatus::ObjectNotFound => {
-                        /* server obj not found */
-                        message_sender_tx
-                            .send(UbusMsg {
-                                header: UbusMsgHeader {
-                                    version: UbusMsgVersion::CURRENT,
-                                    cmd_type: UbusCmdType::STATUS,
-                                    sequence: message.header.sequence,
-                                    peer: message.header.peer,
-                                },
-                                ubus_blobs: vec![
-                                    UbusBlob::ObjId(requested_server_obj_id),
-                                    UbusBlob::Status(UbusMsgStatus::NOT_FOUND),
-                                ],
-                            })
-                            .await
-                            .inspect_err(|_| {
-                                log::warn!(
-                                    "failed to send reply because the message_receiver is down"
-                                )
-                            })
-                            .ok();
+                        (Some(reply), UbusMsgStatus::OK)
                     }
+                    FindMethodStatus::MethodNotFound => (None, UbusMsgStatus::METHOD_NOT_FOUND),
+                    FindMethodStatus::ObjectNotFound => (None, UbusMsgStatus::NOT_FOUND),
+                };
+                /* e.g. events, the callback runs but no one waits for its result */
+                if invoke.no_reply {
+                    return;
                 }
+                /* the fd of the reply is passed along with the final STATUS */
+                let (reply_data, reply_fd) = match reply {
+                    Some(UbusReply { data, fd }) => (Some(data), fd),
+                    None => (None, None),
+                };
+                let queued = requester.queue(|protocol| {
+                    if let Some(data) = reply_data {
+                        protocol.reply_data(&invoke, data)?;
+                    }
+                    protocol.reply_status(&invoke, status)
+                });
+                let result = match queued {
+                    Ok(((), messages)) => requester.transmit(messages, reply_fd).await,
+                    Err(e) => Err(e),
+                };
+                result
+                    .inspect_err(|e| log::warn!("failed to reply {}: {}", invoke.method, e))
+                    .ok();
             });
         }
     }
@@ -714,89 +698,110 @@ impl Connection {
     /**
      * message_manager, previously i combine them with tokio::select(), but i don' know is it safe,
      * as the select docs says read_exact and write_all are not cancellation safe and can lead to loss of data
+     *
+     * messages are framed here instead of `Protocol::receive()`, so the fd passed along stays with its message
      */
     async fn run_message_receiver<R: AsyncIoReader>(
         mut io_reader: R,
-        reply_receivers_tx: Arc<RwLock<HashMap<u16, mpsc::Sender<UbusMsg>>>>,
-        invoke_receiver_tx: mpsc::Sender<UbusMsg>,
+        requester: Requester,
+        invoke_receiver_tx: mpsc::Sender<(UbusInvoke, Option<OwnedFd>)>,
+        hello_tx: oneshot::Sender<HexU32>,
+        options: ParseOptions,
     ) {
+        /* only the first HELLO is meaningful */
+        let mut hello_tx = Some(hello_tx);
+        /* reused for every message, replies can be hundreds of KB */
+        let mut buffer = Vec::new();
         loop {
-            let Ok(message) = UbusMsg::from_io(&mut io_reader).await else {
-                panic!(
-                    "failed to read from io, maybe ubusd got shutdown? {}",
-                    std::io::Error::last_os_error()
-                );
-            };
-
-            // dbg!(&message);
-            log::trace!("got message: {:?}", &message);
-            match message.header.cmd_type {
-                UbusCmdType::INVOKE => {
-                    invoke_receiver_tx
-                        .send(message)
-                        .await
-                        .expect("failed to send because invoke_handler crashed!");
+            let raw =
+                match UbusMsg::read_raw_with_options(&mut io_reader, &mut buffer, options).await {
+                    Ok(raw) => raw,
+                    /* the oversized message is skipped, still in sync */
+                    Err(e @ UbusError::LimitExceeded { .. }) => {
+                        log::warn!("drop message: {}", e);
+                        drop(io_reader.take_fd());
+                        continue;
+                    }
+                    Err(_) => panic!(
+                        "failed to read from io, maybe ubusd got shutdown? {}",
+                        std::io::Error::last_os_error()
+                    ),
+                };
+            /* ubusd only passes fd along with INVOKE and STATUS */
+            let mut fd = io_reader.take_fd();
+            /* the DATA of `invoke_raw()` is never parsed, its STATUS still goes through `Protocol` */
+            if raw.header.cmd_type == UbusCmdType::DATA
+                && let Some(data) = raw.find_blob(UbusBlobType::DATA)
+            {
+                let sequence = u16::from(raw.header.sequence);
+                let handler = requester.raw_handlers.lock().unwrap().remove(&sequence);
+                if let Some(handler) = handler {
+                    handler(MsgTableRef::with_options(data, options));
+                    continue;
                 }
-                UbusCmdType::HELLO => {
-                    log::trace!(
-                        "new connection to ubus got HELLO! my client_id is {:08x}",
-                        message.header.peer
-                    );
+            }
+            /* the whole message is read, so a malformed one can be dropped without losing sync */
+            let message = match UbusMsg::from_raw_with_options(raw, options) {
+                Ok(message) => message,
+                Err(e) => {
+                    log::warn!("drop malformed message {:?}: {}", raw, e);
+                    continue;
                 }
-                /*
-                 * if server receive UbusCmdType::NOTIFY, it's ubus tell server that a client subscribers/unsubscribes
-                 * client will receive a UbusCmdType::INVOKE if got notified
-                 */
-                UbusCmdType::NOTIFY => {
-                    log::info!(
-                        "client {} try to {}",
-                        if let Some(id) = message.get_attr_obj_id() {
-                            format!("{:08x}", id)
-                        } else {
-                            "<UNKNOWN>".into()
-                        },
-                        if let Some(active) = message.get_attr_active() {
-                            if active { "subscribe" } else { "unsubscribe" }
-                        } else {
-                            "<UNKNOWN>"
+            };
+
+            let events = {
+                let mut protocol = requester.protocol.lock().unwrap();
+                protocol.handle_message(message);
+                core::iter::from_fn(|| protocol.poll_event()).collect::<Vec<_>>()
+            };
+            for event in events {
+                match event {
+                    ProtocolEvent::Hello(client_id) => {
+                        if let Some(hello_tx) = hello_tx.take() {
+                            let _ = hello_tx.send(client_id);
                         }
-                    );
-                }
-                /*
-                 *
-                 */
-                UbusCmdType::STATUS | UbusCmdType::DATA => {
-                    let seq = message.header.sequence;
-                    let receiver =
-                        if let Some(receiver) = reply_receivers_tx.read().await.get(&seq.into()) {
-                            Some(receiver.clone())
-                        } else {
-                            None
-                        };
-                    if let Some(receiver) = receiver {
-                        let _ = receiver.send(message).await
-                            .inspect_err(|e| {
-                                log::trace!("try to send to reply_receivers_rx[{}] but is dropped, rx may not care about messages any more", seq);
+                    }
+                    ProtocolEvent::Reply { sequence, result } => {
+                        let waiter = requester.reply_waiters.lock().unwrap().remove(&sequence);
+                        if let Some(waiter) = waiter {
+                            let _ = waiter.send((result, fd.take())).inspect_err(|_| {
+                                log::trace!("reply of seq {} is not cared any more", sequence);
                             });
-                    };
-                }
-                _ => {
-                    log::warn!(
-                        "receive a message which doesn't know how to handle: {:?}",
-                        &message
-                    );
+                        }
+                    }
+                    ProtocolEvent::Invoke(invoke) => {
+                        invoke_receiver_tx
+                            .send((invoke, fd.take()))
+                            .await
+                            .expect("failed to send because invoke_handler crashed!");
+                    }
+                    ProtocolEvent::Notify { obj_id, active } => {
+                        log::info!(
+                            "client {} try to {}",
+                            if let Some(id) = obj_id {
+                                format!("{:08x}", id)
+                            } else {
+                                "<UNKNOWN>".into()
+                            },
+                            if let Some(active) = active {
+                                if active { "subscribe" } else { "unsubscribe" }
+                            } else {
+                                "<UNKNOWN>"
+                            }
+                        );
+                    }
                 }
             }
         }
     }
     async fn run_message_sender<W: AsyncIoWriter>(
         mut io_writer: W,
-        mut message_sender_rx: mpsc::Receiver<UbusMsg>,
+        mut message_sender_rx: mpsc::Receiver<TransmitWithFd>,
     ) {
         loop {
-            if let Some(message) = message_sender_rx.recv().await {
+            if let Some((message, fd)) = message_sender_rx.recv().await {
                 io_writer
-                    .put(&message.to_bytes())
+                    .put_with_fd(&message, fd)
                     .await
                     .expect("failed to send to IO, maybe ubusd got shutdown?")
             } else {
@@ -804,83 +809,140 @@ impl Connection {
             }
         }
     }
-    async fn send_message_and_handle_reply(
+}
+
+impl Requester {
+    fn state(&self) -> ConnectionState {
+        if self.failed.load(Ordering::Relaxed) {
+            ConnectionState::Failed
+        } else {
+            ConnectionState::Alive
+        }
+    }
+
+    /**
+     * let `Protocol` encode messages, and take them out before anyone else locks it
+     */
+    fn queue<T>(
         &self,
-        request_cmd_type: UbusCmdType,
-        request_peer: HexU32,
-        request_blobs: Vec<UbusBlob>,
+        f: impl FnOnce(&mut Protocol) -> Result<T, UbusError>,
+    ) -> Result<(T, Vec<Vec<u8>>), UbusError> {
+        let mut protocol = self.protocol.lock().unwrap();
+        let result = f(&mut protocol)?;
+        Ok((
+            result,
+            core::iter::from_fn(|| protocol.poll_transmit()).collect(),
+        ))
+    }
+
+    /**
+     * write messages in order, `fd` goes along with the last one
+     */
+    async fn transmit(
+        &self,
+        messages: Vec<Vec<u8>>,
+        mut fd: Option<OwnedFd>,
+    ) -> Result<(), UbusError> {
+        let last = messages.len().saturating_sub(1);
+        for (idx, message) in messages.into_iter().enumerate() {
+            let fd = if idx == last { fd.take() } else { None };
+            self.message_sender_tx
+                .send((message, fd))
+                .await
+                .inspect_err(|_| log::warn!("failed to send because message_sender crashed!"))
+                .map_err(|_| UbusError::UnexpectChannelClosed())?;
+        }
+        Ok(())
+    }
+
+    async fn send_message(&self, message: UbusMsg) -> Result<(), UbusError> {
+        let ((), messages) = self.queue(|protocol| protocol.send(&message))?;
+        self.transmit(messages, None).await
+    }
+
+    async fn request(
+        &self,
+        request: impl FnOnce(&mut Protocol) -> Result<u16, UbusError>,
     ) -> Result<Vec<Vec<UbusBlob>>, UbusError> {
-        let new_request_sequence = self.generate_new_request_sequence();
-        let (reply_receiver_tx, mut reply_receiver_rx) = mpsc::channel::<UbusMsg>(4);
-        self.reply_receivers_tx
-            .write()
+        self.request_with_fd(request, None)
             .await
-            .insert(new_request_sequence.into(), reply_receiver_tx);
-
-        self.send_message(UbusMsg {
-            header: UbusMsgHeader {
-                version: UbusMsgVersion::CURRENT,
-                cmd_type: request_cmd_type,
-                sequence: new_request_sequence,
-                peer: u32::from(request_peer).into(),
-            },
-            ubus_blobs: request_blobs,
-        })
-        .await?;
-
-        let mut data_blobs = Vec::with_capacity(2);
-        let status = 'messages: loop {
-            match timeout(Duration::from_millis(3000), reply_receiver_rx.recv()).await {
-                Ok(Some(message)) => {
-                    match message.header.cmd_type {
-                        UbusCmdType::STATUS => {
-                            /*
-                             * remove the channel to save memory, e.g. channel(8) x 65535 consume ~100MB
-                             */
-                            self.reply_receivers_tx
-                                .write()
-                                .await
-                                .remove(&new_request_sequence.into());
-
-                            for blob in message.ubus_blobs {
-                                match blob {
-                                    UbusBlob::Status(UbusMsgStatus::OK) => {
-                                        break 'messages Ok(());
-                                    }
-                                    UbusBlob::Status(status) => {
-                                        break 'messages Err(UbusError::Status(status));
-                                    }
-                                    /* when NOTIFY, the response by ubusd doesn't contain a Status... wtf */
-                                    _ => break 'messages Ok(()),
-                                }
-                            }
-                            // break 'messages Err(UbusError::InvalidData("Invalid status message"));
-                        }
-                        UbusCmdType::DATA => {
-                            data_blobs.push(message.ubus_blobs);
-                            // break 'messages Err(UbusError::InvalidData("Invalid data message"));
-                        }
-                        _ => {
-                            log::warn!(
-                                "receive a message which doesn't know how to handle: {:?}",
-                                &message
-                            );
-                        }
-                    }
-                }
-                Ok(None) => {
-                    log::warn!("the reply_receiver_tx disappears?! this shouldn't happen!");
-                    break 'messages Err(UbusError::UnexpectChannelClosed());
-                }
-                Err(_) => {
-                    log::warn!("waiting for long time but not got a status");
-                    break 'messages Err(UbusError::ReplyTimeout());
-                }
+            .map(|(data_blobs, _)| data_blobs)
+    }
+
+    /**
+     * send a request whose DATA goes to `handler` instead of being parsed
+     */
+    async fn request_raw(
+        &self,
+        request: impl FnOnce(&mut Protocol) -> Result<u16, UbusError>,
+        handler: RawReplyHandler,
+    ) -> Result<(), UbusError> {
+        self.request_inner(request, None, Some(handler), REPLY_TIMEOUT)
+            .await
+            .map(drop)
+    }
+
+    /**
+     * send a request (optionally with an fd), wait until `Protocol` got its STATUS,
+     * the fd passed along with STATUS is returned too
+     */
+    async fn request_with_fd(
+        &self,
+        request: impl FnOnce(&mut Protocol) -> Result<u16, UbusError>,
+        request_fd: Option<OwnedFd>,
+    ) -> Result<(Vec<Vec<UbusBlob>>, Option<OwnedFd>), UbusError> {
+        self.request_inner(request, request_fd, None, REPLY_TIMEOUT)
+            .await
+    }
+
+    async fn request_inner(
+        &self,
+        request: impl FnOnce(&mut Protocol) -> Result<u16, UbusError>,
+        request_fd: Option<OwnedFd>,
+        raw_handler: Option<RawReplyHandler>,
+        reply_timeout: Duration,
+    ) -> Result<(Vec<Vec<UbusBlob>>, Option<OwnedFd>), UbusError> {
+        if self.state() == ConnectionState::Failed {
+            return Err(UbusError::ConnectionFailed());
+        }
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let (sequence, messages) = self.queue(request)?;
+        /* wait before sending, or an instant reply finds no one */
+        if let Some(handler) = raw_handler {
+            self.raw_handlers.lock().unwrap().insert(sequence, handler);
+        }
+        self.reply_waiters
+            .lock()
+            .unwrap()
+            .insert(sequence, reply_tx);
+        if let Err(e) = self.transmit(messages, request_fd).await {
+            self.forget(sequence);
+            return Err(e);
+        }
+
+        let reply = timeout(reply_timeout, reply_rx).await;
+        /* not taken if no DATA came */
+        self.raw_handlers.lock().unwrap().remove(&sequence);
+        match reply {
+            Ok(Ok((result, reply_fd))) => result.map(|data_blobs| (data_blobs, reply_fd)),
+            Ok(Err(_)) => {
+                log::warn!("the reply_waiter disappears?! this shouldn't happen!");
+                Err(UbusError::UnexpectChannelClosed())
+            }
+            Err(_) => {
+                log::warn!("waiting for long time but not got a status");
+                self.forget(sequence);
+                Err(UbusError::ReplyTimeout())
             }
-        };
-        match status {
-            Ok(_) => Ok(data_blobs),
-            Err(e) => Err(e),
         }
     }
+
+    /**
+     * give up a request, a late reply is dropped by `Protocol`
+     */
+    fn forget(&self, sequence: u16) {
+        self.reply_waiters.lock().unwrap().remove(&sequence);
+        self.raw_handlers.lock().unwrap().remove(&sequence);
+        self.protocol.lock().unwrap().cancel(sequence);
+    }
 }
@@ -0,0 +1,212 @@
+use alloc::format;
+use alloc::string::String;
+
+use crate::{BlobMsg, BlobMsgPayload, MsgTable};
+
+/**
+ * `JsonFormatter` prints BlobMsgs byte-for-byte like `blobmsg_format_json_indent()` in libblobmsg_json,
+ * so the output can be diffed against the C `ubus` cli
+ *
+ * the differences to serde_json are on purpose:
+ *  - indentation is a tab, `"name": value` has a space only when indented
+ *  - doubles are printed with `%lf`, e.g. `0.500000`
+ *  - empty tables are `{\n\t\n}` when indented
+ *  - INT8 is printed as bool (they share type 7), unknown types are skipped
+ *  - `Bytes` which aren't UTF-8 get U+FFFD, C would print them raw
+ *
+ * ```
+ * use ubus::{JsonFormatter, MsgTable};
+ * let table = MsgTable::try_from(r#"{"list": [], "ratio": 0.5}"#).unwrap();
+ * assert_eq!(JsonFormatter::compact().format_table(&table), r#"{"list":[],"ratio":0.500000}"#);
+ * assert_eq!(table.to_string_ubus(), "{\n\t\"list\": [\n\t\t\n\t],\n\t\"ratio\": 0.500000\n}");
+ * ```
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonFormatter {
+    /* `None` is `blobmsg_format_json()`, `Some(level)` is `blobmsg_format_json_indent(.., level)` */
+    indent: Option<usize>,
+    types: bool,
+}
+
+/**
+ * libubox can't indent deeper than the tabs it has in `add_separator()`
+ */
+const MAX_INDENT: usize = 20;
+
+impl JsonFormatter {
+    /**
+     * one line, like `ubus -S call`
+     */
+    pub fn compact() -> Self {
+        Self {
+            indent: None,
+            types: false,
+        }
+    }
+
+    /**
+     * tab indented starting from `level`, `ubus call` uses 0
+     */
+    pub fn indented(level: usize) -> Self {
+        Self {
+            indent: Some(level),
+            types: false,
+        }
+    }
+
+    /**
+     * annotate scalars with their blobmsg type, e.g. `1500 /* int32 */`, the output isn't JSON anymore
+     */
+    pub fn types(self, types: bool) -> Self {
+        Self { types, ..self }
+    }
+
+    /**
+     * a table without its own name, same as the C side formatting a `blob_buf` with `list = true`
+     */
+    pub fn format_table(&self, table: &MsgTable) -> String {
+        let mut printer = self.printer();
+        printer.list(&table.0, false);
+        printer.out
+    }
+
+    /**
+     * a single BlobMsg with its name, same as the C side with `list = false`
+     */
+    pub fn format_blobmsg(&self, blobmsg: &BlobMsg) -> String {
+        let mut printer = self.printer();
+        printer.element(&blobmsg.name, &blobmsg.data, false);
+        printer.out
+    }
+
+    /**
+     * only the value, no name is printed, e.g. an element of an array or `blobmsg_format_element()` on its own
+     */
+    pub fn format_payload(&self, payload: &BlobMsgPayload) -> String {
+        let mut printer = self.printer();
+        printer.element("", payload, true);
+        printer.out
+    }
+
+    fn printer(&self) -> Printer {
+        Printer {
+            out: String::new(),
+            indent: self.indent.is_some(),
+            level: self.indent.unwrap_or(0),
+            types: self.types,
+        }
+    }
+}
+
+impl MsgTable {
+    /**
+     * what `ubus call` prints (without the trailing newline), see `JsonFormatter`
+     */
+    pub fn to_string_ubus(&self) -> String {
+        JsonFormatter::indented(0).format_table(self)
+    }
+}
+
+struct Printer {
+    out: String,
+    indent: bool,
+    level: usize,
+    types: bool,
+}
+
+impl Printer {
+    /* `add_separator()` */
+    fn separator(&mut self) {
+        if self.indent {
+            self.out.push('\n');
+            (0..self.level.min(MAX_INDENT)).for_each(|_| self.out.push('\t'));
+        }
+    }
+
+    /* `blobmsg_format_json_list()` */
+    fn list(&mut self, blobmsgs: &[BlobMsg], array: bool) {
+        self.out.push(if array { '[' } else { '{' });
+        self.level += 1;
+        self.separator();
+        for (idx, blobmsg) in blobmsgs.iter().enumerate() {
+            if idx > 0 {
+                self.out.push(',');
+                self.separator();
+            }
+            self.element(&blobmsg.name, &blobmsg.data, array);
+        }
+        self.level -= 1;
+        self.separator();
+        self.out.push(if array { ']' } else { '}' });
+    }
+
+    /* `blobmsg_format_element()` */
+    fn element(&mut self, name: &str, payload: &BlobMsgPayload, without_name: bool) {
+        /* `blobmsg_check_attr()` rejects types beyond BLOBMSG_TYPE_LAST, the element is dropped */
+        if matches!(payload, BlobMsgPayload::Unknown(id, _) if *id != 0) {
+            return;
+        }
+        if !without_name && !name.is_empty() {
+            self.string(name);
+            self.out.push_str(if self.indent { ": " } else { ":" });
+        }
+        let (value, type_name) = match payload {
+            BlobMsgPayload::Array(list) => return self.list(list, true),
+            BlobMsgPayload::Table(table) => return self.list(table, false),
+            BlobMsgPayload::String(s) => {
+                self.string(s);
+                return self.annotate("string");
+            }
+            /* C prints the bytes as they are, we only have `String` */
+            BlobMsgPayload::Bytes(bytes) => {
+                self.string(&String::from_utf8_lossy(bytes));
+                return self.annotate("string");
+            }
+            BlobMsgPayload::Null | BlobMsgPayload::Unknown(..) => ("null".into(), "unspec"),
+            BlobMsgPayload::Bool(b) => (format!("{b}"), "bool"),
+            BlobMsgPayload::Int8(v) => (format!("{}", *v != 0), "int8"),
+            BlobMsgPayload::Int16(v) => (format!("{v}"), "int16"),
+            BlobMsgPayload::Int32(v) => (format!("{v}"), "int32"),
+            BlobMsgPayload::Int64(v) => (format!("{v}"), "int64"),
+            BlobMsgPayload::Double(f) => (format_double(*f), "double"),
+        };
+        self.out.push_str(&value);
+        self.annotate(type_name);
+    }
+
+    fn annotate(&mut self, type_name: &str) {
+        if self.types {
+            self.out.push_str(" /* ");
+            self.out.push_str(type_name);
+            self.out.push_str(" */");
+        }
+    }
+
+    /* `blobmsg_format_string()`, C strings end at the first nul */
+    fn string(&mut self, s: &str) {
+        let s = s.split('\0').next().unwrap_or_default();
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '\u{8}' => self.out.push_str("\\b"),
+                '\n' => self.out.push_str("\\n"),
+                '\t' => self.out.push_str("\\t"),
+                '\r' => self.out.push_str("\\r"),
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                c if c < ' ' => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+/* `printf("%lf")` of glibc */
+fn format_double(f: f64) -> String {
+    if f.is_nan() {
+        if f.is_sign_negative() { "-nan" } else { "nan" }.into()
+    } else {
+        format!("{f:.6}")
+    }
+}
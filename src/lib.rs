@@ -1,9 +1,9 @@
 #![no_std]
 #![allow(dead_code)]
 #![feature(iterator_try_collect)]
-#![feature(random)]
 
-#[cfg(not(no_std))]
+extern crate alloc;
+#[cfg(feature = "std")]
 extern crate std;
 
 /**
@@ -13,26 +13,52 @@ extern crate std;
  * - Better Readibility
  * - Tests
  */
-/* communicate with ubusd */
+/* communicate with ubusd without a runtime, needs feature `std` on unix */
+#[cfg(all(feature = "std", unix))]
+pub mod blocking;
+/* communicate with ubusd, needs feature `tokio` */
+#[cfg(feature = "tokio")]
 mod connection;
+#[cfg(feature = "tokio")]
+mod ubusobj;
+#[cfg(feature = "tokio")]
 mod usock;
 /* the types used in ubus and convertion between raw bytes and rust types  */
 mod blob;
+mod blobattr;
 mod blobmsg;
+mod blobmsgpolicy;
+mod blobmsgref;
+mod jsonformat;
+mod msgtable;
+mod protocol;
+mod ubusacl;
 mod ubusblob;
 mod ubusmsg;
-mod ubusobj;
 /* utilities */
+mod parseopts;
 mod ubuserror;
 mod utils;
 
 pub use blob::*;
+pub use blobattr::*;
 pub use blobmsg::*;
+pub use blobmsgpolicy::*;
+pub use blobmsgref::*;
+#[cfg(feature = "tokio")]
 pub use connection::*;
+pub use jsonformat::*;
+pub use msgtable::*;
+pub use parseopts::*;
+pub use protocol::*;
+pub use ubusacl::*;
 pub use ubusblob::*;
 pub use ubuserror::*;
 pub use ubusmsg::*;
+#[cfg(feature = "tokio")]
 pub use ubusobj::*;
+#[cfg(feature = "tokio")]
+pub use usock::*;
 // pub use utils::*;
 
 // use crate::values;
@@ -0,0 +1,495 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{BlobMsg, BlobMsgPayload, MsgTable};
+
+/**
+ * map-like access to `MsgTable`, working on the blobmsg tree directly so types (e.g. INT16 vs INT32) are kept
+ *
+ * blobmsg tables are ordered and may contain the same name twice, lookups return the first match
+ *
+ * ```
+ * use ubus::{BlobMsgPayload, MsgTable};
+ * let mut table = MsgTable::try_from(r#"{"ipv4-address":[{"address":"192.168.1.1","mask":24}]}"#).unwrap();
+ * assert_eq!(table.query_str("ipv4-address[0].address"), Some("192.168.1.1"));
+ * table.insert("up", BlobMsgPayload::Bool(true));
+ * assert_eq!(table.get_bool("up"), Some(true));
+ * ```
+ */
+impl MsgTable {
+    /**
+     * borrow the payload of a nested table or array as `MsgTable`
+     */
+    #[allow(clippy::ptr_arg)]
+    pub fn from_vec(blobmsgs: &Vec<BlobMsg>) -> &MsgTable {
+        /* SAFETY: MsgTable is #[repr(transparent)] over Vec<BlobMsg> */
+        unsafe { &*(blobmsgs as *const Vec<BlobMsg> as *const MsgTable) }
+    }
+
+    pub fn from_vec_mut(blobmsgs: &mut Vec<BlobMsg>) -> &mut MsgTable {
+        /* SAFETY: MsgTable is #[repr(transparent)] over Vec<BlobMsg> */
+        unsafe { &mut *(blobmsgs as *mut Vec<BlobMsg> as *mut MsgTable) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BlobMsgPayload> {
+        self.position(name).map(|idx| &self.0[idx].data)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut BlobMsgPayload> {
+        self.position(name).map(|idx| &mut self.0[idx].data)
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(BlobMsgPayload::as_str)
+    }
+
+    /**
+     * any integer type, see `BlobMsgPayload::as_i64()`
+     */
+    pub fn get_i64(&self, name: &str) -> Option<i64> {
+        self.get(name).and_then(BlobMsgPayload::as_i64)
+    }
+
+    /**
+     * any integer type, see `BlobMsgPayload::as_u64()`
+     */
+    pub fn get_u64(&self, name: &str) -> Option<u64> {
+        self.get(name).and_then(BlobMsgPayload::as_u64)
+    }
+
+    pub fn get_bytes(&self, name: &str) -> Option<&[u8]> {
+        self.get(name).and_then(BlobMsgPayload::as_bytes)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        self.get(name).and_then(BlobMsgPayload::as_bool)
+    }
+
+    pub fn get_table(&self, name: &str) -> Option<&MsgTable> {
+        self.get(name).and_then(BlobMsgPayload::as_table)
+    }
+
+    pub fn get_array(&self, name: &str) -> Option<&MsgTable> {
+        self.get(name).and_then(BlobMsgPayload::as_array)
+    }
+
+    /**
+     * follow a path like `"ipv4-address[0].address"`, names are separated by `.` and `[n]` indexes arrays (or tables)
+     *
+     * names containing `.` or `[` can't be reached this way, use `get()` step by step
+     */
+    pub fn query(&self, path: &str) -> Option<&BlobMsgPayload> {
+        let mut steps = parse_path(path)?.into_iter();
+        let mut payload = match steps.next()? {
+            PathStep::Name(name) => self.get(name)?,
+            PathStep::Index(_) => return None,
+        };
+        for step in steps {
+            let nested = match payload {
+                BlobMsgPayload::Table(nested) | BlobMsgPayload::Array(nested) => nested,
+                _ => return None,
+            };
+            payload = match step {
+                PathStep::Name(name) => MsgTable::from_vec(nested).get(name)?,
+                PathStep::Index(idx) => &nested.get(idx)?.data,
+            };
+        }
+        Some(payload)
+    }
+
+    pub fn query_mut(&mut self, path: &str) -> Option<&mut BlobMsgPayload> {
+        let mut steps = parse_path(path)?.into_iter();
+        let mut payload = match steps.next()? {
+            PathStep::Name(name) => self.get_mut(name)?,
+            PathStep::Index(_) => return None,
+        };
+        for step in steps {
+            let nested = match payload {
+                BlobMsgPayload::Table(nested) | BlobMsgPayload::Array(nested) => nested,
+                _ => return None,
+            };
+            payload = match step {
+                PathStep::Name(name) => MsgTable::from_vec_mut(nested).get_mut(name)?,
+                PathStep::Index(idx) => &mut nested.get_mut(idx)?.data,
+            };
+        }
+        Some(payload)
+    }
+
+    pub fn query_str(&self, path: &str) -> Option<&str> {
+        self.query(path).and_then(BlobMsgPayload::as_str)
+    }
+
+    pub fn query_i64(&self, path: &str) -> Option<i64> {
+        self.query(path).and_then(BlobMsgPayload::as_i64)
+    }
+
+    /**
+     * replace the payload of the first `name`, or append a new BlobMsg at the end, the old payload is returned
+     */
+    pub fn insert(&mut self, name: &str, payload: BlobMsgPayload) -> Option<BlobMsgPayload> {
+        match self.get_mut(name) {
+            Some(old) => Some(core::mem::replace(old, payload)),
+            None => {
+                self.0.push(BlobMsg {
+                    name: name.into(),
+                    data: payload,
+                });
+                None
+            }
+        }
+    }
+
+    /**
+     * remove the first `name`, the order of the others is kept
+     */
+    pub fn remove(&mut self, name: &str) -> Option<BlobMsgPayload> {
+        self.position(name).map(|idx| self.0.remove(idx).data)
+    }
+
+    pub fn entry(&mut self, name: &str) -> MsgTableEntry<'_> {
+        MsgTableEntry {
+            idx: self.position(name),
+            name: name.into(),
+            table: self,
+        }
+    }
+
+    /**
+     * names and payloads in order, duplicated names included
+     */
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &BlobMsgPayload)> {
+        self.0
+            .iter()
+            .map(|blobmsg| (blobmsg.name.as_str(), &blobmsg.data))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &mut BlobMsgPayload)> {
+        self.0
+            .iter_mut()
+            .map(|blobmsg| (blobmsg.name.as_str(), &mut blobmsg.data))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|blobmsg| blobmsg.name.as_str())
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.0.iter().position(|blobmsg| blobmsg.name == name)
+    }
+}
+
+impl IntoIterator for MsgTable {
+    type Item = BlobMsg;
+    type IntoIter = alloc::vec::IntoIter<BlobMsg>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MsgTable {
+    type Item = &'a BlobMsg;
+    type IntoIter = core::slice::Iter<'a, BlobMsg>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<BlobMsg> for MsgTable {
+    fn from_iter<T: IntoIterator<Item = BlobMsg>>(iter: T) -> Self {
+        MsgTable(iter.into_iter().collect())
+    }
+}
+
+impl Extend<BlobMsg> for MsgTable {
+    fn extend<T: IntoIterator<Item = BlobMsg>>(&mut self, iter: T) {
+        self.0.extend(iter)
+    }
+}
+
+/**
+ * a place in `MsgTable` for `name`, which may be taken already, from `MsgTable::entry()`
+ */
+pub struct MsgTableEntry<'a> {
+    table: &'a mut MsgTable,
+    name: String,
+    /* index of the first BlobMsg named `name`, None if vacant */
+    idx: Option<usize>,
+}
+
+impl<'a> MsgTableEntry<'a> {
+    pub fn is_occupied(&self) -> bool {
+        self.idx.is_some()
+    }
+
+    pub fn or_insert(self, default: BlobMsgPayload) -> &'a mut BlobMsgPayload {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with(
+        self,
+        default: impl FnOnce() -> BlobMsgPayload,
+    ) -> &'a mut BlobMsgPayload {
+        let idx = match self.idx {
+            Some(idx) => idx,
+            None => {
+                self.table.0.push(BlobMsg {
+                    name: self.name,
+                    data: default(),
+                });
+                self.table.0.len() - 1
+            }
+        };
+        &mut self.table.0[idx].data
+    }
+
+    pub fn and_modify(self, f: impl FnOnce(&mut BlobMsgPayload)) -> Self {
+        if let Some(idx) = self.idx {
+            f(&mut self.table.0[idx].data);
+        }
+        self
+    }
+}
+
+impl BlobMsgPayload {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            BlobMsgPayload::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /**
+     * raw bytes of a STRING, whether it's UTF-8 (`String`) or not (`Bytes`)
+     */
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            BlobMsgPayload::String(s) => Some(s.as_bytes()),
+            BlobMsgPayload::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /**
+     * BOOL, or INT8 when decoded with `ParseOptions::int8_as_bool` off, like `blobmsg_get_bool()`
+     */
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            BlobMsgPayload::Bool(b) => Some(b),
+            BlobMsgPayload::Int8(v) => Some(v != 0),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            BlobMsgPayload::Double(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&MsgTable> {
+        match self {
+            BlobMsgPayload::Table(table) => Some(MsgTable::from_vec(table)),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&MsgTable> {
+        match self {
+            BlobMsgPayload::Array(array) => Some(MsgTable::from_vec(array)),
+            _ => None,
+        }
+    }
+}
+
+enum PathStep<'p> {
+    Name(&'p str),
+    Index(usize),
+}
+
+/**
+ * `"a.b[0][1].c"` -> Name(a), Name(b), Index(0), Index(1), Name(c)
+ */
+fn parse_path(path: &str) -> Option<Vec<PathStep<'_>>> {
+    let mut steps = Vec::new();
+    for segment in path.split('.') {
+        let (name, mut indexes) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        if !name.is_empty() {
+            steps.push(PathStep::Name(name));
+        } else if indexes.is_empty() {
+            return None;
+        }
+        while !indexes.is_empty() {
+            let (idx, rest) = indexes.strip_prefix('[')?.split_once(']')?;
+            steps.push(PathStep::Index(idx.parse().ok()?));
+            indexes = rest;
+        }
+    }
+    Some(steps)
+}
+
+/**
+ * build a `MsgTable` with JSON-like syntax, Rust types decide the blobmsg types
+ *
+ * `1500i32` is INT32, `1500i16` is INT16, an untyped `1500` is `i32` like in Rust,
+ * `{...}` is a table, `[...]` is an array, `null` is UNSPEC, anything else goes through `BlobMsgPayload::from()`
+ *
+ * ```
+ * use ubus::{msgtable, BlobMsgPayload};
+ * let mtu: i64 = 1500;
+ * let table = msgtable! {
+ *     "name": "lan",
+ *     "mtu": mtu,
+ *     "metric": 10i16,
+ *     "up": true,
+ *     "ips": ["192.168.1.1", "10.0.0.1"],
+ *     "dns": { "search": null },
+ * };
+ * assert!(matches!(table.get("mtu"), Some(BlobMsgPayload::Int64(1500))));
+ * assert!(matches!(table.get("metric"), Some(BlobMsgPayload::Int16(10))));
+ * ```
+ */
+#[macro_export]
+macro_rules! msgtable {
+    /* table entries, munched one by one into `$out` */
+    (@table [$($out:expr,)*] ()) => {
+        $crate::__blobmsgs([$($out,)*])
+    };
+    (@table [$($out:expr,)*] ($key:literal : {$($value:tt)*} $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@table [$($out,)* $crate::__blobmsg($key, $crate::msgtable!(@payload {$($value)*})),] ($($($rest)*)?))
+    };
+    (@table [$($out:expr,)*] ($key:literal : [$($value:tt)*] $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@table [$($out,)* $crate::__blobmsg($key, $crate::msgtable!(@payload [$($value)*])),] ($($($rest)*)?))
+    };
+    (@table [$($out:expr,)*] ($key:literal : null $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@table [$($out,)* $crate::__blobmsg($key, $crate::BlobMsgPayload::Null),] ($($($rest)*)?))
+    };
+    (@table [$($out:expr,)*] ($key:literal : $value:expr $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@table [$($out,)* $crate::__blobmsg($key, $crate::BlobMsgPayload::from($value)),] ($($($rest)*)?))
+    };
+
+    /* array elements, names are empty */
+    (@array [$($out:expr,)*] ()) => {
+        $crate::__blobmsgs([$($out,)*])
+    };
+    (@array [$($out:expr,)*] ({$($value:tt)*} $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@array [$($out,)* $crate::__blobmsg("", $crate::msgtable!(@payload {$($value)*})),] ($($($rest)*)?))
+    };
+    (@array [$($out:expr,)*] ([$($value:tt)*] $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@array [$($out,)* $crate::__blobmsg("", $crate::msgtable!(@payload [$($value)*])),] ($($($rest)*)?))
+    };
+    (@array [$($out:expr,)*] (null $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@array [$($out,)* $crate::__blobmsg("", $crate::BlobMsgPayload::Null),] ($($($rest)*)?))
+    };
+    (@array [$($out:expr,)*] ($value:expr $(, $($rest:tt)*)?)) => {
+        $crate::msgtable!(@array [$($out,)* $crate::__blobmsg("", $crate::BlobMsgPayload::from($value)),] ($($($rest)*)?))
+    };
+
+    (@payload {$($value:tt)*}) => {
+        $crate::BlobMsgPayload::Table($crate::msgtable!(@table [] ($($value)*)))
+    };
+    (@payload [$($value:tt)*]) => {
+        $crate::BlobMsgPayload::Array($crate::msgtable!(@array [] ($($value)*)))
+    };
+
+    ($($entries:tt)*) => {
+        $crate::MsgTable($crate::msgtable!(@table [] ($($entries)*)))
+    };
+}
+
+#[doc(hidden)]
+pub fn __blobmsg(name: &str, data: BlobMsgPayload) -> BlobMsg {
+    BlobMsg {
+        name: name.into(),
+        data,
+    }
+}
+
+#[doc(hidden)]
+pub fn __blobmsgs<const N: usize>(blobmsgs: [BlobMsg; N]) -> Vec<BlobMsg> {
+    blobmsgs.into()
+}
+
+/* Rust types to BlobMsgPayload with the same width, unsigned ones keep their bits like `blobmsg_add_u32()` */
+macro_rules! payload_from_number {
+    ( $( $ty:ty => $variant:ident as $as:ty , )* ) => { $(
+        impl From<$ty> for BlobMsgPayload {
+            fn from(value: $ty) -> Self {
+                BlobMsgPayload::$variant(value as $as)
+            }
+        }
+    )* };
+}
+payload_from_number!(
+    i8 => Int8 as i8,
+    i16 => Int16 as i16,
+    i32 => Int32 as i32,
+    i64 => Int64 as i64,
+    u8 => Int8 as i8,
+    u16 => Int16 as i16,
+    u32 => Int32 as i32,
+    u64 => Int64 as i64,
+    f32 => Double as f64,
+    f64 => Double as f64,
+);
+
+impl From<bool> for BlobMsgPayload {
+    fn from(value: bool) -> Self {
+        BlobMsgPayload::Bool(value)
+    }
+}
+
+impl From<&str> for BlobMsgPayload {
+    fn from(value: &str) -> Self {
+        BlobMsgPayload::String(value.into())
+    }
+}
+
+impl From<String> for BlobMsgPayload {
+    fn from(value: String) -> Self {
+        BlobMsgPayload::String(value)
+    }
+}
+
+/**
+ * binary data, sent as STRING
+ */
+impl From<Vec<u8>> for BlobMsgPayload {
+    fn from(value: Vec<u8>) -> Self {
+        BlobMsgPayload::Bytes(value)
+    }
+}
+
+impl From<&[u8]> for BlobMsgPayload {
+    fn from(value: &[u8]) -> Self {
+        BlobMsgPayload::Bytes(value.into())
+    }
+}
+
+impl From<MsgTable> for BlobMsgPayload {
+    fn from(value: MsgTable) -> Self {
+        BlobMsgPayload::Table(value.0)
+    }
+}
+
+/**
+ * `None` is `null`
+ */
+impl<T: Into<BlobMsgPayload>> From<Option<T>> for BlobMsgPayload {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(BlobMsgPayload::Null, Into::into)
+    }
+}
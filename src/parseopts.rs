@@ -0,0 +1,115 @@
+use crate::UbusError;
+
+/**
+ * ubusd drops messages larger than this, see `UBUS_MAX_MSGLEN` in ubusmsg.h
+ */
+pub const UBUS_MAX_MSGLEN: usize = 1048576;
+
+/**
+ * how deep tables and arrays can nest, libubox has no limit but real data rarely goes beyond 10
+ */
+pub const BLOBMSG_MAX_DEPTH: usize = 64;
+
+/**
+ * `ParseOptions` controls how bytes from peers are decoded, the defaults match what ubusd accepts
+ *
+ * limits protect us from a corrupt (or hostile) peer, e.g. a broken header claiming a 16 MiB message
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /**
+     * message size including `UbusMsgHeader`, larger messages are skipped without allocating
+     */
+    pub max_message_size: usize,
+    /**
+     * nesting of tables and arrays, the `Data` table of a message counts as 1
+     */
+    pub max_depth: usize,
+    /**
+     * skip malformed blobs with a warning instead of failing the whole message, like the ubus cli
+     */
+    pub lenient: bool,
+    /**
+     * `BLOBMSG_TYPE_BOOL` and `BLOBMSG_TYPE_INT8` share the id 7, decode it as `Bool` like libblobmsg_json,
+     * or as `Int8` to keep the number, e.g. for a counter from a C daemon
+     */
+    pub int8_as_bool: bool,
+    /**
+     * what to do with STRINGs which aren't UTF-8, e.g. SSIDs from hostapd
+     */
+    pub utf8: Utf8Policy,
+}
+
+/**
+ * blobmsg STRINGs are plain C strings, nothing on wire promises they are UTF-8
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /**
+     * fail the message with `UbusError::InvalidBlob`
+     */
+    #[default]
+    Strict,
+    /**
+     * replace invalid sequences with U+FFFD, names included
+     */
+    Lossy,
+    /**
+     * keep the bytes as `BlobMsgPayload::Bytes`, names are decoded lossy,
+     * plain blob STRINGs become `BlobAttrPayload::Binary` with their NUL
+     */
+    Bytes,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_message_size: UBUS_MAX_MSGLEN,
+            max_depth: BLOBMSG_MAX_DEPTH,
+            lenient: false,
+            int8_as_bool: true,
+            utf8: Utf8Policy::Strict,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn max_message_size(self, max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            ..self
+        }
+    }
+
+    pub fn max_depth(self, max_depth: usize) -> Self {
+        Self { max_depth, ..self }
+    }
+
+    pub fn lenient(self, lenient: bool) -> Self {
+        Self { lenient, ..self }
+    }
+
+    pub fn int8_as_bool(self, int8_as_bool: bool) -> Self {
+        Self {
+            int8_as_bool,
+            ..self
+        }
+    }
+
+    pub fn utf8(self, utf8: Utf8Policy) -> Self {
+        Self { utf8, ..self }
+    }
+
+    /**
+     * options for parsing one level deeper
+     */
+    pub(crate) fn nested(self) -> Result<Self, UbusError> {
+        match self.max_depth.checked_sub(1) {
+            Some(max_depth) => Ok(Self { max_depth, ..self }),
+            None => Err(UbusError::LimitExceeded {
+                what: "nesting depth",
+                max: self.max_depth,
+            }),
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::ops::Not;
+
+use crate::{
+    BlobMsg, BlobMsgPayload, BlobTag, HexU32, MsgTable, ParseOptions, UbusBlob, UbusCmdType,
+    UbusError, UbusMsg, UbusMsgHeader, UbusMsgRef, UbusMsgStatus, UbusMsgVersion,
+};
+
+/**
+ * where libubus puts ubusd's socket, can be overridden with the `UBUS_SOCKET` environment variable
+ */
+pub const UBUS_DEFAULT_SOCKET: &str = "/var/run/ubus/ubus.sock";
+
+/**
+ * `Protocol` is a ubus connection without IO, so it can be driven by any event loop (mio, glib, uloop...)
+ *
+ *  - feed bytes read from the socket to `receive()`, then take what happened from `poll_event()`
+ *  - requests (`lookup()`, `invoke()`...) return their sequence, the result comes as `ProtocolEvent::Reply`
+ *  - write everything from `poll_transmit()` to the socket, one message each, fds go along with the message
+ *    that needs them (the request of `invoke_with_fd()`, the STATUS of a reply)
+ *
+ * timeouts are up to the driver, `cancel()` a request it gives up on. `Connection` is the tokio driver
+ *
+ * ```
+ * use ubus::*;
+ * fn message(cmd_type: UbusCmdType, sequence: u16, ubus_blobs: Vec<UbusBlob>) -> Vec<u8> {
+ *     let header = UbusMsgHeader {
+ *         version: UbusMsgVersion::CURRENT,
+ *         cmd_type,
+ *         sequence: sequence.into(),
+ *         peer: 0x1234.into(),
+ *     };
+ *     UbusMsg::from_header_and_blobs(&header, ubus_blobs).to_bytes()
+ * }
+ * let mut protocol = Protocol::new();
+ * protocol.receive(&message(UbusCmdType::HELLO, 0, vec![])).unwrap();
+ * assert!(matches!(protocol.poll_event(), Some(ProtocolEvent::Hello(_))));
+ *
+ * let sequence = protocol.lookup("network").unwrap();
+ * let request = protocol.poll_transmit().unwrap();
+ * assert_eq!(request[1], UbusCmdType::LOOKUP.value());
+ *
+ * /* what ubusd replies, split anywhere */
+ * let mut reply = message(UbusCmdType::DATA, sequence, vec![UbusBlob::ObjPath("network".into())]);
+ * reply.extend(message(UbusCmdType::STATUS, sequence, vec![UbusBlob::Status(UbusMsgStatus::OK)]));
+ * let (head, tail) = reply.split_at(7);
+ * protocol.receive(head).unwrap();
+ * assert!(protocol.poll_event().is_none());
+ * protocol.receive(tail).unwrap();
+ * let Some(ProtocolEvent::Reply { result: Ok(objects), .. }) = protocol.poll_event() else {
+ *     panic!("no reply");
+ * };
+ * assert_eq!(UbusObject::from_blobs(objects[0].clone()).path, "network");
+ * ```
+ */
+#[derive(Debug)]
+pub struct Protocol {
+    options: ParseOptions,
+    client_id: Option<HexU32>,
+    sequence: u16,
+    /* requests waiting for a STATUS, with blobs of DATA replies got so far */
+    pending: BTreeMap<u16, Vec<Vec<UbusBlob>>>,
+    /* a message not completely received yet */
+    received: Vec<u8>,
+    /* bytes of an oversized message still to be thrown away */
+    discard: usize,
+    transmits: VecDeque<Vec<u8>>,
+    events: VecDeque<ProtocolEvent>,
+}
+
+#[derive(Debug)]
+pub enum ProtocolEvent {
+    /**
+     * ubusd says HELLO right after connect, with the client_id of this connection
+     */
+    Hello(HexU32),
+    /**
+     * a request got its STATUS, `Ok` has the blobs of every DATA reply before it
+     */
+    Reply {
+        sequence: u16,
+        result: Result<Vec<Vec<UbusBlob>>, UbusError>,
+    },
+    /**
+     * a client calls a method of our server object, or an object we subscribed notifies,
+     * answer with `reply_data()` and `reply_status()`
+     */
+    Invoke(UbusInvoke),
+    /**
+     * ubusd tells our server object that a subscriber comes (`active`) or goes
+     */
+    Notify {
+        obj_id: Option<HexU32>,
+        active: Option<bool>,
+    },
+}
+
+/**
+ * an INVOKE received
+ *
+ *  - `sequence`, `peer` : the client's session, used as they are when reply
+ *  - `obj_id`           : our server object, same as the id `add_object()` got
+ */
+#[derive(Debug, Clone)]
+pub struct UbusInvoke {
+    pub sequence: u16,
+    pub peer: HexU32,
+    pub obj_id: HexU32,
+    pub method: String,
+    pub args: MsgTable,
+    /**
+     * user and group of the client process, added by ubusd
+     */
+    pub user: Option<String>,
+    pub group: Option<String>,
+    /**
+     * the caller doesn't wait, don't reply
+     */
+    pub no_reply: bool,
+}
+
+/**
+ * used in lookup
+ */
+#[derive(Default, Debug, Clone)]
+pub struct UbusObject {
+    pub path: String,
+    pub id: HexU32,
+    pub objtype: HexU32,
+    /**
+     * used on client side lookup, store what the server says
+     */
+    pub reported_signature: MsgTable,
+}
+
+impl UbusObject {
+    /**
+     * an object from the blobs of a LOOKUP reply, or of an ADD_OBJECT reply (which has no path)
+     */
+    pub fn from_blobs(ubus_blobs: Vec<UbusBlob>) -> Self {
+        let mut obj = UbusObject::default();
+        for ubus_blob in ubus_blobs {
+            match ubus_blob {
+                UbusBlob::ObjPath(path) => obj.path = path,
+                UbusBlob::ObjId(id) => obj.id = id,
+                UbusBlob::ObjType(ty) => obj.objtype = ty,
+                UbusBlob::Signature(nested) => obj.reported_signature = nested,
+                /* attributes of newer ubusd */
+                _ => {}
+            }
+        }
+        obj
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self::with_options(ParseOptions::default())
+    }
+}
+
+impl Protocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * incoming messages are decoded with `options`, messages beyond the limits are dropped with a warning
+     */
+    pub fn with_options(options: ParseOptions) -> Self {
+        Self {
+            options,
+            client_id: None,
+            sequence: 0,
+            pending: BTreeMap::new(),
+            received: Vec::new(),
+            discard: 0,
+            transmits: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /**
+     * told by the first HELLO
+     */
+    pub fn client_id(&self) -> Option<HexU32> {
+        self.client_id
+    }
+
+    /**
+     * bytes read from the socket, any amount, a message may be split or several may come at once
+     *
+     * an error means the stream is out of sync (e.g. a wrong version), the connection should be closed
+     */
+    pub fn receive(&mut self, mut data: &[u8]) -> Result<(), UbusError> {
+        let skipped = self.discard.min(data.len());
+        self.discard -= skipped;
+        data = &data[skipped..];
+        self.received.extend_from_slice(data);
+
+        let mut consumed = 0;
+        let result = loop {
+            let buffer = &self.received[consumed..];
+            if buffer.len() < UbusMsgHeader::SIZE + BlobTag::SIZE {
+                break Ok(());
+            }
+            let header =
+                UbusMsgHeader::from_bytes(buffer[..UbusMsgHeader::SIZE].try_into().unwrap());
+            if header.version != UbusMsgVersion::CURRENT {
+                break Err(UbusError::InvalidData("Wrong version"));
+            }
+            let tag = BlobTag::from_bytes(
+                &buffer[UbusMsgHeader::SIZE..][..BlobTag::SIZE]
+                    .try_into()
+                    .unwrap(),
+            );
+            if let Err(e) = tag.is_valid() {
+                break Err(e);
+            }
+            let len = UbusMsgHeader::SIZE + tag.size();
+            /* don't keep an oversized message, throw it away as it comes */
+            if len > self.options.max_message_size {
+                log::warn!(
+                    "drop message: {}",
+                    UbusError::LimitExceeded {
+                        what: "message size",
+                        max: self.options.max_message_size,
+                    }
+                );
+                let available = len.min(buffer.len());
+                self.discard = len - available;
+                consumed += available;
+                continue;
+            }
+            if buffer.len() < len {
+                break Ok(());
+            }
+            let message = UbusMsgRef::from_bytes_with_options(&buffer[..len], self.options)
+                .and_then(|raw| UbusMsg::from_raw_with_options(raw, self.options));
+            consumed += len;
+            match message {
+                Ok(message) => self.handle_message(message),
+                /* the whole message is here, so a malformed one can be dropped without losing sync */
+                Err(e) => log::warn!("drop malformed message: {}", e),
+            }
+        };
+        self.received.drain(..consumed);
+        if result.is_err() {
+            self.received.clear();
+        }
+        result
+    }
+
+    /**
+     * a message already framed by the driver, e.g. with `UbusMsg::read_raw()` to get fds passed along
+     */
+    pub fn handle_message(&mut self, message: UbusMsg) {
+        log::trace!("got message: {:?}", message);
+        let sequence = u16::from(message.header.sequence);
+        match message.header.cmd_type {
+            UbusCmdType::HELLO => {
+                let client_id = u32::from(message.header.peer).into();
+                log::trace!("got HELLO! my client_id is {:?}", client_id);
+                self.client_id.get_or_insert(client_id);
+                self.events.push_back(ProtocolEvent::Hello(client_id));
+            }
+            UbusCmdType::INVOKE => match Self::invoke_of(message) {
+                Some(invoke) => self.events.push_back(ProtocolEvent::Invoke(invoke)),
+                None => log::warn!("can't get obj_id, method_name, and req_args from INVOKE"),
+            },
+            /*
+             * if server receive UbusCmdType::NOTIFY, it's ubus tell server that a client subscribers/unsubscribes
+             * client will receive a UbusCmdType::INVOKE if got notified
+             */
+            UbusCmdType::NOTIFY => self.events.push_back(ProtocolEvent::Notify {
+                obj_id: message.get_attr_obj_id().map(HexU32),
+                active: message.get_attr_active(),
+            }),
+            UbusCmdType::DATA => match self.pending.get_mut(&sequence) {
+                Some(data_blobs) => data_blobs.push(message.ubus_blobs),
+                None => log::trace!("drop DATA of seq {}, no one waits for it", sequence),
+            },
+            UbusCmdType::STATUS => match self.pending.remove(&sequence) {
+                Some(data_blobs) => {
+                    /* the STATUS of NOTIFY doesn't contain a Status... */
+                    let result = match message.get_attr_status() {
+                        None | Some(UbusMsgStatus::OK) => Ok(data_blobs),
+                        Some(status) => Err(UbusError::Status(status)),
+                    };
+                    self.events
+                        .push_back(ProtocolEvent::Reply { sequence, result });
+                }
+                None => log::trace!("drop STATUS of seq {}, no one waits for it", sequence),
+            },
+            _ => log::warn!(
+                "receive a message which doesn't know how to handle: {:?}",
+                message
+            ),
+        }
+    }
+
+    pub fn poll_event(&mut self) -> Option<ProtocolEvent> {
+        self.events.pop_front()
+    }
+
+    /**
+     * the next message to write
+     */
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmits.pop_front()
+    }
+
+    /**
+     * send a message as it is, its replies are not tracked
+     */
+    pub fn send(&mut self, message: &UbusMsg) -> Result<(), UbusError> {
+        let mut buffer = Vec::new();
+        message.encode_into(&mut buffer)?;
+        self.transmits.push_back(buffer);
+        Ok(())
+    }
+
+    /**
+     * send a request with a new sequence, its DATA replies are collected until the STATUS
+     */
+    pub fn request(
+        &mut self,
+        cmd_type: UbusCmdType,
+        peer: HexU32,
+        ubus_blobs: Vec<UbusBlob>,
+    ) -> Result<u16, UbusError> {
+        /* sequence identifies the session, servers reply with the sequence of the request */
+        self.sequence = self.sequence.wrapping_add(1);
+        let sequence = self.sequence;
+        self.send(&UbusMsg {
+            header: UbusMsgHeader {
+                version: UbusMsgVersion::CURRENT,
+                cmd_type,
+                sequence: sequence.into(),
+                peer: u32::from(peer).into(),
+            },
+            ubus_blobs,
+        })?;
+        self.pending.insert(sequence, Vec::new());
+        Ok(sequence)
+    }
+
+    /**
+     * forget a request, e.g. timed out, a late reply is dropped
+     */
+    pub fn cancel(&mut self, sequence: u16) -> bool {
+        self.pending.remove(&sequence).is_some()
+    }
+
+    pub fn is_pending(&self, sequence: u16) -> bool {
+        self.pending.contains_key(&sequence)
+    }
+
+    /**
+     * how many requests are still waiting for their reply
+     */
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn ping(&mut self) -> Result<u16, UbusError> {
+        self.request(UbusCmdType::PING, 0.into(), Vec::new())
+    }
+
+    /**
+     * an empty path lists all objects, the reply has a DATA for each object, see `UbusObject::from_blobs()`
+     */
+    pub fn lookup(&mut self, obj_path: &str) -> Result<u16, UbusError> {
+        self.request(
+            UbusCmdType::LOOKUP,
+            0.into(),
+            obj_path
+                .is_empty()
+                .not()
+                .then(|| UbusBlob::ObjPath(obj_path.to_string()))
+                .into_iter()
+                .collect(),
+        )
+    }
+
+    /**
+     * the reply data is found by `data_of()`
+     */
+    pub fn invoke(
+        &mut self,
+        server_obj_id: HexU32,
+        method: &str,
+        args: MsgTable,
+    ) -> Result<u16, UbusError> {
+        self.request(
+            UbusCmdType::INVOKE,
+            server_obj_id,
+            vec![
+                UbusBlob::ObjId(server_obj_id),
+                UbusBlob::Method(method.to_string()),
+                UbusBlob::Data(args),
+            ],
+        )
+    }
+
+    /**
+     * ubusd replies a STATUS for each subscriber, only the first one finishes the request
+     */
+    pub fn notify(
+        &mut self,
+        server_obj_id: HexU32,
+        method: &str,
+        data: MsgTable,
+    ) -> Result<u16, UbusError> {
+        self.request(
+            UbusCmdType::NOTIFY,
+            server_obj_id,
+            vec![
+                UbusBlob::ObjId(server_obj_id),
+                UbusBlob::Method(method.into()),
+                UbusBlob::Data(data),
+            ],
+        )
+    }
+
+    pub fn subscribe(
+        &mut self,
+        listener_obj_id: HexU32,
+        server_obj_id: HexU32,
+    ) -> Result<u16, UbusError> {
+        self.request(
+            UbusCmdType::SUBSCRIBE,
+            0.into(),
+            vec![
+                UbusBlob::ObjId(listener_obj_id),
+                UbusBlob::Target(server_obj_id),
+            ],
+        )
+    }
+
+    /**
+     * the id and type of the new object are in the reply, see `UbusObject::from_blobs()`
+     *
+     * an empty path adds an anonymous object, e.g. a subscriber or an event listener,
+     * it can't be looked up but gets INVOKEs all the same
+     */
+    pub fn add_object<'m>(
+        &mut self,
+        obj_path: &str,
+        methods: impl IntoIterator<Item = &'m str>,
+    ) -> Result<u16, UbusError> {
+        let signature = UbusBlob::Signature(
+            methods
+                .into_iter()
+                .map(|method| BlobMsg {
+                    name: method.to_string(),
+                    data: BlobMsgPayload::Table(Vec::new()),
+                })
+                .collect::<Vec<BlobMsg>>()
+                .into(),
+        );
+        self.request(
+            UbusCmdType::ADD_OBJECT,
+            0.into(),
+            obj_path
+                .is_empty()
+                .not()
+                .then(|| UbusBlob::ObjPath(obj_path.to_string()))
+                .into_iter()
+                .chain([signature])
+                .collect(),
+        )
+    }
+
+    /**
+     * reply data to an INVOKE, a `reply_status()` must follow to finish it
+     */
+    pub fn reply_data(&mut self, invoke: &UbusInvoke, data: MsgTable) -> Result<(), UbusError> {
+        self.send(&Self::reply_of(
+            invoke,
+            UbusCmdType::DATA,
+            UbusBlob::Data(data),
+        ))
+    }
+
+    pub fn reply_status(
+        &mut self,
+        invoke: &UbusInvoke,
+        status: UbusMsgStatus,
+    ) -> Result<(), UbusError> {
+        self.send(&Self::reply_of(
+            invoke,
+            UbusCmdType::STATUS,
+            UbusBlob::Status(status),
+        ))
+    }
+
+    /**
+     * the data of the first DATA reply, e.g. the result of `invoke()`
+     */
+    pub fn data_of(data_blobs: Vec<Vec<UbusBlob>>) -> Option<MsgTable> {
+        data_blobs
+            .into_iter()
+            .flatten()
+            .find_map(|ubus_blob| match ubus_blob {
+                UbusBlob::Data(data) => Some(data),
+                _ => None,
+            })
+    }
+
+    /* here client_obj_id == server objid */
+    fn reply_of(invoke: &UbusInvoke, cmd_type: UbusCmdType, ubus_blob: UbusBlob) -> UbusMsg {
+        UbusMsg {
+            header: UbusMsgHeader {
+                version: UbusMsgVersion::CURRENT,
+                cmd_type,
+                sequence: invoke.sequence.into(),
+                peer: u32::from(invoke.peer).into(),
+            },
+            ubus_blobs: vec![UbusBlob::ObjId(invoke.obj_id), ubus_blob],
+        }
+    }
+
+    fn invoke_of(message: UbusMsg) -> Option<UbusInvoke> {
+        let mut obj_id = None;
+        let mut method = None;
+        let mut args = None;
+        let mut user = None;
+        let mut group = None;
+        let mut no_reply = false;
+        for ubus_blob in message.ubus_blobs {
+            match ubus_blob {
+                UbusBlob::ObjId(id) => obj_id = Some(id),
+                UbusBlob::Method(name) => method = Some(name),
+                UbusBlob::Data(data) => args = Some(data),
+                UbusBlob::User(name) => user = Some(name),
+                UbusBlob::Group(name) => group = Some(name),
+                UbusBlob::NoReply(value) => no_reply = value,
+                _ => {}
+            }
+        }
+        Some(UbusInvoke {
+            sequence: message.header.sequence.into(),
+            peer: u32::from(message.header.peer).into(),
+            obj_id: obj_id?,
+            method: method?,
+            args: args?,
+            user,
+            group,
+            no_reply,
+        })
+    }
+}
+
+/**
+ * ubusd denies by ACL with a plain status, make it more meaningful
+ */
+pub(crate) fn permission_denied_of(e: UbusError, server_obj_id: HexU32, method: &str) -> UbusError {
+    match e {
+        UbusError::Status(UbusMsgStatus::PERMISSION_DENIED) => UbusError::PermissionDenied {
+            object: format!("{:08x}", server_obj_id),
+            method: method.to_string(),
+        },
+        e => e,
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{BlobMsgPayload, MsgTable, UbusError};
+use alloc::{string::String, vec::Vec};
+
+/**
+ * objects built into ubusd, they exist without ADD_OBJECT
+ *  - `UBUS_SYSTEM_OBJECT_EVENT`    : `register` event handlers and `send` events
+ *  - `UBUS_SYSTEM_OBJECT_ACL`      : `query` the ACLs of objects owned by the caller
+ */
+pub const UBUS_SYSTEM_OBJECT_EVENT: u32 = 1;
+pub const UBUS_SYSTEM_OBJECT_ACL: u32 = 2;
+
+/**
+ * ubusd sends this event when ACL files in `/usr/share/acl.d` got reloaded,
+ * servers should `Connection::query_acl()` again once it's seen
+ */
+pub const UBUS_ACL_SEQUENCE_EVENT: &str = "ubus.acl.sequence";
+
+/**
+ * one rule from `/usr/share/acl.d`, telling an object can be accessed by a user or group
+ *
+ * ubusd only reports rules with an `"acl"` field, which is private data for the server,
+ * e.g. `{"user": "network", "access": {"myobj": {"methods": ["*"], "acl": {"level": "read"}}}}`
+ */
+#[derive(Debug, Clone, Default)]
+pub struct UbusAclEntry {
+    pub object: String,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub acl: MsgTable,
+}
+
+/**
+ * ACLs of all server objects owned by this connection, reply of `Connection::query_acl()`
+ *
+ * on wire: `{"seq": 1, "acl": [{"obj": "myobj", "user": "network", "acl": {...}}, ...]}`
+ */
+#[derive(Debug, Clone, Default)]
+pub struct UbusAclTable {
+    /**
+     * increased by ubusd on each reload, compare with the one in `UBUS_ACL_SEQUENCE_EVENT`
+     */
+    pub sequence: u32,
+    pub entries: Vec<UbusAclEntry>,
+}
+
+impl UbusAclTable {
+    /**
+     * find rules of `object` which apply to the caller, use with `UbusRequest::user`/`UbusRequest::group`
+     *
+     * a rule without user (or group) matches any user (or group)
+     */
+    pub fn lookup<'a>(
+        &'a self,
+        object: &'a str,
+        user: Option<&'a str>,
+        group: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a UbusAclEntry> + 'a {
+        self.entries.iter().filter(move |entry| {
+            entry.object == object
+                && (entry.user.is_none() || entry.user.as_deref() == user)
+                && (entry.group.is_none() || entry.group.as_deref() == group)
+        })
+    }
+}
+
+impl TryFrom<MsgTable> for UbusAclTable {
+    type Error = UbusError;
+    fn try_from(table: MsgTable) -> Result<Self, Self::Error> {
+        let mut acl_table = UbusAclTable::default();
+        for blobmsg in table.0 {
+            match (blobmsg.name.as_str(), blobmsg.data) {
+                ("seq", BlobMsgPayload::Int32(seq)) => acl_table.sequence = seq as u32,
+                ("seq", BlobMsgPayload::Int16(seq)) => acl_table.sequence = seq as u32,
+                ("seq", BlobMsgPayload::Int64(seq)) => acl_table.sequence = seq as u32,
+                ("acl", BlobMsgPayload::Array(rules)) => {
+                    for rule in rules {
+                        let BlobMsgPayload::Table(fields) = rule.data else {
+                            return Err(UbusError::InvalidData("ACL rule is not a table"));
+                        };
+                        let mut entry = UbusAclEntry::default();
+                        for field in fields {
+                            match (field.name.as_str(), field.data) {
+                                ("obj", BlobMsgPayload::String(object)) => entry.object = object,
+                                ("user", BlobMsgPayload::String(user)) => entry.user = Some(user),
+                                ("group", BlobMsgPayload::String(group)) => {
+                                    entry.group = Some(group)
+                                }
+                                ("acl", BlobMsgPayload::Table(acl)) => entry.acl = acl.into(),
+                                _ => {}
+                            }
+                        }
+                        acl_table.entries.push(entry);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(acl_table)
+    }
+}
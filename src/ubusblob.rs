@@ -1,10 +1,10 @@
 use crate::{
-    Blob, BlobBuilder, BlobPayloadParser, BlobTag, MsgTable, UbusError, UbusMsgStatus, valid_data,
-    values,
+    Blob, BlobBuffer, BlobBuilder, BlobPayloadParser, BlobTag, MsgTable, ParseOptions, UbusError,
+    UbusMsgStatus, parse_nested, valid_data, values,
 };
+use alloc::{string::String, vec::Vec};
 use core::fmt::{LowerHex, UpperHex};
 use serde::{Deserialize, Serialize};
-use std::{borrow::ToOwned, string::String, vec::Vec};
 
 values!(pub UbusBlobType(u32) {
     UNSPEC      = 0x00,
@@ -67,6 +67,13 @@ pub enum UbusBlob {
     Subscribers(MsgTable),
     User(String),
     Group(String),
+    /**
+     * attribute added by a newer ubusd, kept as is so it's re-encoded unchanged
+     */
+    Unknown {
+        id: u32,
+        bytes: Vec<u8>,
+    },
 }
 
 // impl core::fmt::Debug for UbusBlob {
@@ -96,7 +103,9 @@ impl TryFrom<Blob> for UbusBlob {
     fn try_from(value: Blob) -> Result<Self, Self::Error> {
         match value {
             Blob::UbusBlob(blob) => Ok(blob),
-            Blob::BlogMsg(_) => Err(UbusError::InvalidData("")),
+            Blob::BlogMsg(_) => Err(UbusError::InvalidData(
+                "BlobMsg found where UbusBlob expected",
+            )),
         }
     }
 }
@@ -104,12 +113,7 @@ impl TryFrom<Blob> for UbusBlob {
 impl TryFrom<&[u8]> for UbusBlob {
     type Error = UbusError;
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        valid_data!(data.len() >= BlobTag::SIZE, "Blob too short");
-        // Read the blob's tag
-        let (tag, data) = data.split_at(BlobTag::SIZE);
-        let tag = BlobTag::from_bytes(tag.try_into().unwrap());
-        // dbg!(&tag, &data);
-        Self::from_tag_and_data(tag, data)
+        Self::from_bytes_with_options(data, ParseOptions::default())
     }
 }
 
@@ -117,7 +121,26 @@ impl UbusBlob {
     pub fn from_bytes(data: &[u8]) -> Result<Self, UbusError> {
         data.try_into()
     }
+    /**
+     * same as `UbusBlob::try_from()`, with limits in `options`
+     */
+    pub fn from_bytes_with_options(data: &[u8], options: ParseOptions) -> Result<Self, UbusError> {
+        valid_data!(data.len() >= BlobTag::SIZE, "Blob too short");
+        // Read the blob's tag
+        let (tag, data) = data.split_at(BlobTag::SIZE);
+        let tag = BlobTag::from_bytes(tag.try_into().unwrap());
+        // dbg!(&tag, &data);
+        Self::from_tag_and_data_with_options(tag, data, options)
+            .map_err(|e| e.at(0, tag.blob_type()))
+    }
     pub fn from_tag_and_data(tag: BlobTag, data: &[u8]) -> Result<Self, UbusError> {
+        Self::from_tag_and_data_with_options(tag, data, ParseOptions::default())
+    }
+    pub fn from_tag_and_data_with_options(
+        tag: BlobTag,
+        data: &[u8],
+        options: ParseOptions,
+    ) -> Result<Self, UbusError> {
         tag.is_valid()?;
         // dbg!(tag, data);
         valid_data!(data.len() >= tag.inner_len(), "Blob too short");
@@ -125,6 +148,12 @@ impl UbusBlob {
         // Restrict data to payload size
         let data = &data[..tag.inner_len()];
         let parser = BlobPayloadParser::from(data);
+        /* the tag is followed by BlobMsgs directly, so tables are located from the tag */
+        let table = |data| {
+            parse_nested(data, options)
+                .map(MsgTable)
+                .map_err(|e| e.at(BlobTag::SIZE, tag.blob_type()))
+        };
 
         let _len = tag.inner_len();
         let _type = tag.blob_type();
@@ -136,15 +165,18 @@ impl UbusBlob {
             UbusBlobType::OBJID => Ok(UbusBlob::ObjId(parser.try_into()?)),
             UbusBlobType::METHOD => Ok(UbusBlob::Method(parser.try_into()?)),
             UbusBlobType::OBJTYPE => Ok(UbusBlob::ObjType(parser.try_into()?)),
-            UbusBlobType::SIGNATURE => Ok(UbusBlob::Signature(parser.try_into()?)),
-            UbusBlobType::DATA => Ok(UbusBlob::Data(parser.try_into()?)),
+            UbusBlobType::SIGNATURE => Ok(UbusBlob::Signature(table(data)?)),
+            UbusBlobType::DATA => Ok(UbusBlob::Data(table(data)?)),
             UbusBlobType::TARGET => Ok(UbusBlob::Target(parser.try_into()?)),
             UbusBlobType::ACTIVE => Ok(UbusBlob::Active(parser.try_into()?)),
             UbusBlobType::NO_REPLY => Ok(UbusBlob::NoReply(parser.try_into()?)),
-            UbusBlobType::SUBSCRIBERS => Ok(UbusBlob::Subscribers(parser.try_into()?)),
+            UbusBlobType::SUBSCRIBERS => Ok(UbusBlob::Subscribers(table(data)?)),
             UbusBlobType::USER => Ok(UbusBlob::User(parser.try_into()?)),
             UbusBlobType::GROUP => Ok(UbusBlob::Group(parser.try_into()?)),
-            unknown_type => Err(UbusError::InvalidBlobType(unknown_type)),
+            unknown_type => Ok(UbusBlob::Unknown {
+                id: unknown_type.value(),
+                bytes: parser.into(),
+            }),
         }
     }
 
@@ -154,64 +186,42 @@ impl UbusBlob {
      * if the data is too long and BlobTag can't build, it may panic, should be rarely
      */
     pub fn to_bytes(&self) -> Vec<u8> {
-        // create payload bytes depending on variant
+        let mut builder = BlobBuilder::new();
+        self.push_to(&mut builder)
+            .expect("only failed if the blob is too large or can't be encoded");
+        builder.into()
+    }
+
+    /**
+     * encode this blob at the end of `builder`, tables are written in place without intermediate buffers
+     */
+    pub fn push_to<B: BlobBuffer>(&self, builder: &mut BlobBuilder<B>) -> Result<(), UbusError> {
         match self {
-            UbusBlob::Unspec(v) => BlobBuilder::from_bytes(UbusBlobType::UNSPEC.value(), v)
-                .unwrap()
-                .into(),
-            UbusBlob::Status(v) => BlobBuilder::from_u32(UbusBlobType::STATUS.value(), v.0)
-                .unwrap()
-                .into(),
-            UbusBlob::ObjPath(v) => BlobBuilder::from_str(UbusBlobType::OBJPATH.value(), v)
-                .unwrap()
-                .into(),
-            UbusBlob::ObjId(v) => BlobBuilder::from_u32(UbusBlobType::OBJID.value(), (*v).into())
-                .unwrap()
-                .into(),
-            UbusBlob::Method(v) => BlobBuilder::from_str(UbusBlobType::METHOD.value(), v)
-                .unwrap()
-                .into(),
-            UbusBlob::ObjType(v) => {
-                BlobBuilder::from_u32(UbusBlobType::OBJTYPE.value(), (*v).into())
-                    .unwrap()
-                    .into()
-            }
-            UbusBlob::Signature(v) => {
-                /*  */
-                BlobBuilder::from_bytes(
-                    UbusBlobType::SIGNATURE.value(),
-                    <Vec<u8>>::try_from(v.to_owned()).unwrap().iter(),
-                )
-                .unwrap()
-                .into()
-            }
-            UbusBlob::Data(v) => BlobBuilder::from_bytes(
-                UbusBlobType::DATA.value(),
-                <Vec<u8>>::try_from(v.to_owned()).unwrap().iter(),
-            )
-            .unwrap()
-            .into(),
-            UbusBlob::Target(v) => BlobBuilder::from_u32(UbusBlobType::TARGET.value(), (*v).into())
-                .unwrap()
-                .into(),
-            UbusBlob::Active(v) => BlobBuilder::from_bool(UbusBlobType::ACTIVE.value(), *v)
-                .unwrap()
-                .into(),
-            UbusBlob::NoReply(v) => BlobBuilder::from_bool(UbusBlobType::NO_REPLY.value(), *v)
-                .unwrap()
-                .into(),
-            UbusBlob::Subscribers(v) => BlobBuilder::from_bytes(
-                UbusBlobType::SUBSCRIBERS.value(),
-                <Vec<u8>>::try_from(v.to_owned()).unwrap().iter(),
-            )
-            .unwrap()
-            .into(),
-            UbusBlob::User(v) => BlobBuilder::from_str(UbusBlobType::USER.value(), v)
-                .unwrap()
-                .into(),
-            UbusBlob::Group(v) => BlobBuilder::from_str(UbusBlobType::GROUP.value(), v)
-                .unwrap()
-                .into(),
+            UbusBlob::Unspec(v) => builder.push_bytes(UbusBlobType::UNSPEC.value(), v),
+            UbusBlob::Status(v) => builder.push_u32(UbusBlobType::STATUS.value(), v.0),
+            UbusBlob::ObjPath(v) => builder.push_str(UbusBlobType::OBJPATH.value(), v),
+            UbusBlob::ObjId(v) => builder.push_u32(UbusBlobType::OBJID.value(), (*v).into()),
+            UbusBlob::Method(v) => builder.push_str(UbusBlobType::METHOD.value(), v),
+            UbusBlob::ObjType(v) => builder.push_u32(UbusBlobType::OBJTYPE.value(), (*v).into()),
+            UbusBlob::Signature(v) => push_msg_table(builder, UbusBlobType::SIGNATURE, v),
+            UbusBlob::Data(v) => push_msg_table(builder, UbusBlobType::DATA, v),
+            UbusBlob::Target(v) => builder.push_u32(UbusBlobType::TARGET.value(), (*v).into()),
+            UbusBlob::Active(v) => builder.push_bool(UbusBlobType::ACTIVE.value(), *v),
+            UbusBlob::NoReply(v) => builder.push_bool(UbusBlobType::NO_REPLY.value(), *v),
+            UbusBlob::Subscribers(v) => push_msg_table(builder, UbusBlobType::SUBSCRIBERS, v),
+            UbusBlob::User(v) => builder.push_str(UbusBlobType::USER.value(), v),
+            UbusBlob::Group(v) => builder.push_str(UbusBlobType::GROUP.value(), v),
+            UbusBlob::Unknown { id, bytes } => builder.push_bytes(*id, bytes),
         }
     }
 }
+
+fn push_msg_table<B: BlobBuffer>(
+    builder: &mut BlobBuilder<B>,
+    blob_type: UbusBlobType,
+    table: &MsgTable,
+) -> Result<(), UbusError> {
+    let nest = builder.open_nest(blob_type.value())?;
+    builder.push_msg_table(&table.0)?;
+    builder.close(nest)
+}
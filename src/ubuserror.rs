@@ -1,14 +1,15 @@
-extern crate alloc;
+use alloc::string::{FromUtf8Error, String};
 use core::str::Utf8Error;
-use std::{io, string::FromUtf8Error};
+#[cfg(feature = "std")]
+use std::io;
 
-use alloc::string::String;
 use thiserror::Error;
 
-use crate::UbusBlobType;
+use crate::{BlobMsgType, UbusBlobType};
 
 #[derive(Debug, Error)]
 pub enum UbusError {
+    #[cfg(feature = "std")]
     #[error("io error")]
     IO(#[from] io::Error),
     #[error("Invalid decoding string")]
@@ -17,12 +18,31 @@ pub enum UbusError {
     FromUtf8(#[from] FromUtf8Error),
     #[error("Invalid Data")]
     InvalidData(&'static str),
+    #[error("Invalid blob (type {blob_type}) at offset {offset}: {reason}")]
+    InvalidBlob {
+        /* from the start of the bytes given to the parser, or the start of the message for `UbusMsg` */
+        offset: usize,
+        blob_type: u32,
+        reason: &'static str,
+    },
     #[error("Ubus return ErrorCode({0})")]
     Status(crate::UbusMsgStatus),
+    #[error("Permission denied to call {object}.{method}, check ACLs in /usr/share/acl.d")]
+    PermissionDenied { object: String, method: String },
     #[error("Error parse arguments string:{0}")]
     ParseArguments(#[from] serde_json::Error),
+    #[error("{name} should be {expected}, got {found}")]
+    PolicyMismatch {
+        name: String,
+        expected: BlobMsgType,
+        found: BlobMsgType,
+    },
+    #[error("Duplicate name in table:{0}")]
+    DuplicateName(String),
     #[error("Invalid method:{0}")]
     InvalidMethod(String),
+    #[error("{what} exceeds the limit {max}")]
+    LimitExceeded { what: &'static str, max: usize },
     #[error("Invalid blog type:{0}")]
     InvalidBlobType(UbusBlobType),
     #[error("No such path:{0}")]
@@ -31,7 +51,42 @@ pub enum UbusError {
     UnexpectChannelClosed(),
     #[error("Reply Timeout")]
     ReplyTimeout(),
+    #[error("Connection failed, ubusd stops answering")]
+    ConnectionFailed(),
+}
+
+impl UbusError {
+    /**
+     * tell where a parsing error happens, `offset` is where the blob of `blob_type` starts
+     *
+     * errors already located inside a nested blob are only shifted by `offset`
+     */
+    pub fn at(self, offset: usize, blob_type: u32) -> Self {
+        match self {
+            UbusError::InvalidBlob {
+                offset: inner_offset,
+                blob_type,
+                reason,
+            } => UbusError::InvalidBlob {
+                offset: offset + inner_offset,
+                blob_type,
+                reason,
+            },
+            UbusError::InvalidData(reason) => UbusError::InvalidBlob {
+                offset,
+                blob_type,
+                reason,
+            },
+            UbusError::Utf8(_) | UbusError::FromUtf8(_) => UbusError::InvalidBlob {
+                offset,
+                blob_type,
+                reason: "Invalid UTF-8 string",
+            },
+            e => e,
+        }
+    }
 }
 
 pub trait IOError {}
+#[cfg(feature = "std")]
 impl IOError for std::io::Error {}
@@ -1,10 +1,13 @@
+#[cfg(feature = "tokio")]
 use crate::usock::AsyncIoReader;
-use crate::{BlobIter, BlobTag, UbusBlob, UbusBlobType, UbusError, valid_data, values};
+use crate::{
+    BlobBuffer, BlobBuilder, BlobIter, BlobPayloadParser, BlobTag, MsgTableRef, ParseOptions,
+    UbusBlob, UbusBlobType, UbusError, valid_data, values,
+};
+use alloc::vec::Vec;
 use core::convert::TryInto;
 use core::mem::{size_of, transmute};
 use serde::{Deserialize, Serialize};
-use std::vec;
-use std::vec::Vec;
 use storage_endian::{BEu16, BEu32};
 
 values!(pub UbusMsgVersion(u8) {
@@ -71,8 +74,46 @@ pub struct UbusMsg {
     pub ubus_blobs: Vec<UbusBlob>,
 }
 
+/**
+ * reading from a transport, needs feature `tokio`
+ */
+#[cfg(feature = "tokio")]
 impl UbusMsg {
     pub async fn from_io<T: AsyncIoReader>(io: &mut T) -> Result<Self, UbusError> {
+        Self::from_io_with_options(io, ParseOptions::default()).await
+    }
+
+    pub async fn from_io_with_options<T: AsyncIoReader>(
+        io: &mut T,
+        options: ParseOptions,
+    ) -> Result<Self, UbusError> {
+        let mut buffer = Vec::new();
+        let raw = Self::read_raw_with_options(io, &mut buffer, options).await?;
+        Self::from_raw_with_options(raw, options)
+    }
+
+    /**
+     * read a message into `buffer` without parsing it, the buffer is reused between calls,
+     * so receiving a large reply doesn't allocate again and again
+     *
+     * use `UbusMsgRef::data()` to walk the reply lazily, or `UbusMsg::try_from()` to get an owned copy
+     */
+    pub async fn read_raw<'b, T: AsyncIoReader>(
+        io: &mut T,
+        buffer: &'b mut Vec<u8>,
+    ) -> Result<UbusMsgRef<'b>, UbusError> {
+        Self::read_raw_with_options(io, buffer, ParseOptions::default()).await
+    }
+
+    /**
+     * same as `read_raw()`, a message larger than `options.max_message_size` is read and thrown away
+     * in small chunks, so the stream is still usable after `UbusError::LimitExceeded`
+     */
+    pub async fn read_raw_with_options<'b, T: AsyncIoReader>(
+        io: &mut T,
+        buffer: &'b mut Vec<u8>,
+        options: ParseOptions,
+    ) -> Result<UbusMsgRef<'b>, UbusError> {
         /* read ubus message header */
         let mut ubusmsg_header_buffer = [0u8; UbusMsgHeader::SIZE];
         io.get(&mut ubusmsg_header_buffer).await?;
@@ -85,20 +126,34 @@ impl UbusMsg {
         let tag = BlobTag::from_bytes(&ubusmsg_blob_header_buffer);
         tag.is_valid()?;
 
+        /* don't trust the length before allocating */
+        if UbusMsgHeader::SIZE + tag.size() > options.max_message_size {
+            let mut discard = [0u8; 4096];
+            let mut remaining = tag.inner_len();
+            while remaining > 0 {
+                let len = remaining.min(discard.len());
+                io.get(&mut discard[..len]).await?;
+                remaining -= len;
+            }
+            return Err(UbusError::LimitExceeded {
+                what: "message size",
+                max: options.max_message_size,
+            });
+        }
+
         /* use the length extracted from blob header, read such length of blob data  */
-        let mut ubusmsg_data_buffer = vec![0u8; tag.inner_len()];
-        io.get(&mut ubusmsg_data_buffer).await?;
-        /* the magic parser, convert bytes to Vec<UbusBlob> */
-        let blobs = BlobIter::new(&ubusmsg_data_buffer)
-            .map(|blob| blob.try_into())
-            .try_collect::<Vec<UbusBlob>>()?;
+        buffer.clear();
+        buffer.resize(tag.inner_len(), 0u8);
+        io.get(buffer).await?;
 
-        Ok(UbusMsg {
+        Ok(UbusMsgRef {
             header,
-            ubus_blobs: blobs,
+            blobs: buffer,
         })
     }
+}
 
+impl UbusMsg {
     pub fn from_header_and_blobs(header: &UbusMsgHeader, blobs: Vec<UbusBlob>) -> Self {
         Self {
             header: *header,
@@ -110,6 +165,27 @@ impl UbusMsg {
         self.into()
     }
 
+    /**
+     * append the whole message to `buffer` in a single pass, same as `MsgTable::encode_into()`,
+     * `clear()` it first to reuse the allocation for message after message
+     */
+    pub fn encode_into<B: BlobBuffer>(&self, buffer: &mut B) -> Result<(), UbusError> {
+        buffer.extend_from_slice(&self.header.to_bytes());
+
+        let mut builder = BlobBuilder::with_buffer(core::mem::take(buffer));
+        /* the container blob, its length is patched once all blobs are written */
+        let result = builder
+            .open_nest(UbusBlobType::UNSPEC.value())
+            .and_then(|container| {
+                self.ubus_blobs
+                    .iter()
+                    .try_for_each(|blob| blob.push_to(&mut builder))?;
+                builder.close(container)
+            });
+        *buffer = builder.into_inner();
+        result
+    }
+
     pub fn get_attr_obj_id(&self) -> Option<u32> {
         self.ubus_blobs.iter().find_map(|blob| {
             if let UbusBlob::ObjId(obj_id) = blob {
@@ -139,30 +215,138 @@ impl UbusMsg {
     }
 }
 
-impl From<UbusMsg> for Vec<u8> {
-    fn from(ubus_msg: UbusMsg) -> Self {
-        let ubusmsg_header_buf = ubus_msg.header.to_bytes();
+/**
+ * `UbusMsgRef` is a message still sitting in the receive buffer, got from `UbusMsg::read_raw()` or `from_bytes()`
+ */
+#[derive(Clone, Copy)]
+pub struct UbusMsgRef<'a> {
+    pub header: UbusMsgHeader,
+    /* the payload of the container blob, UbusBlobs laid one by one */
+    pub blobs: &'a [u8],
+}
+
+impl<'a> UbusMsgRef<'a> {
+    /**
+     * a whole message already in memory, e.g. received without tokio, bytes after the message are ignored
+     */
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, UbusError> {
+        Self::from_bytes_with_options(data, ParseOptions::default())
+    }
+
+    pub fn from_bytes_with_options(
+        data: &'a [u8],
+        options: ParseOptions,
+    ) -> Result<Self, UbusError> {
+        valid_data!(
+            data.len() >= UbusMsgHeader::SIZE + BlobTag::SIZE,
+            "Message too short"
+        );
+        let (header, data) = data.split_at(UbusMsgHeader::SIZE);
+        let header = UbusMsgHeader::from_bytes(header.try_into().unwrap());
+        valid_data!(header.version == UbusMsgVersion::CURRENT, "Wrong version");
+
+        let tag = BlobTag::from_bytes(&data[..BlobTag::SIZE].try_into().unwrap());
+        tag.is_valid()?;
+        if UbusMsgHeader::SIZE + tag.size() > options.max_message_size {
+            return Err(UbusError::LimitExceeded {
+                what: "message size",
+                max: options.max_message_size,
+            });
+        }
+        valid_data!(data.len() >= tag.size(), "Message too short");
+        Ok(Self {
+            header,
+            blobs: &data[BlobTag::SIZE..tag.size()],
+        })
+    }
 
-        let mut ubusmsg_blobs_buffer = Vec::new();
-        for blob in ubus_msg.ubus_blobs {
-            ubusmsg_blobs_buffer.extend_from_slice(&blob.to_bytes());
+    /**
+     * payload of the first UbusBlob of `blob_type`, without parsing any other blobs
+     */
+    pub fn find_blob(&self, blob_type: UbusBlobType) -> Option<&'a [u8]> {
+        let mut data = self.blobs;
+        while data.len() >= BlobTag::SIZE {
+            let tag = BlobTag::from_bytes(&data[..BlobTag::SIZE].try_into().unwrap());
+            tag.is_valid().ok()?;
+            let payload = data.get(BlobTag::SIZE..tag.size())?;
+            if !tag.is_extended() && tag.blob_type() == blob_type.value() {
+                return Some(payload);
+            }
+            data = &data[tag.next_tag().min(data.len())..];
         }
+        None
+    }
+
+    /**
+     * the reply data of DATA message, or the arguments of INVOKE message
+     */
+    pub fn data(&self) -> Option<MsgTableRef<'a>> {
+        self.find_blob(UbusBlobType::DATA).map(MsgTableRef::new)
+    }
+
+    pub fn get_attr_status(&self) -> Option<UbusMsgStatus> {
+        self.find_blob(UbusBlobType::STATUS)
+            .and_then(|payload| BlobPayloadParser::from(payload).try_into().ok())
+    }
+}
+
+impl<'a> TryFrom<UbusMsgRef<'a>> for UbusMsg {
+    type Error = UbusError;
+    fn try_from(raw: UbusMsgRef<'a>) -> Result<Self, Self::Error> {
+        Self::from_raw_with_options(raw, ParseOptions::default())
+    }
+}
+
+impl UbusMsg {
+    pub fn from_raw_with_options(
+        raw: UbusMsgRef,
+        options: ParseOptions,
+    ) -> Result<Self, UbusError> {
+        /* the magic parser, convert bytes to Vec<UbusBlob> */
+        let blobs = BlobIter::with_options(raw.blobs, options)
+            .map(|blob| blob?.try_into())
+            .try_collect::<Vec<UbusBlob>>()
+            .map_err(|e| {
+                e.at(
+                    UbusMsgHeader::SIZE + BlobTag::SIZE,
+                    UbusBlobType::UNSPEC.value(),
+                )
+            })?;
 
-        let ubusmsg_blob_header_buffer = BlobTag::try_build(
-            UbusBlobType::UNSPEC.value(),
-            BlobTag::SIZE + ubusmsg_blobs_buffer.len(),
-            false,
+        Ok(UbusMsg {
+            header: raw.header,
+            ubus_blobs: blobs,
+        })
+    }
+}
+
+impl<'a> core::fmt::Debug for UbusMsgRef<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "MessageRef({:?} seq={}, peer={:08x}, blobs_len={})",
+            self.header.cmd_type,
+            self.header.sequence,
+            self.header.peer,
+            self.blobs.len()
         )
-        .expect("only failed if len passed to BlobTag::try_build is too large")
-        .to_bytes();
+    }
+}
 
+impl From<&UbusMsg> for Vec<u8> {
+    fn from(ubus_msg: &UbusMsg) -> Self {
         let mut raw_msg_data = Vec::new();
-        raw_msg_data.extend_from_slice(&ubusmsg_header_buf);
-        raw_msg_data.extend_from_slice(&ubusmsg_blob_header_buffer);
-        raw_msg_data.extend_from_slice(&ubusmsg_blobs_buffer);
+        ubus_msg
+            .encode_into(&mut raw_msg_data)
+            .expect("only failed if the message is too large for BlobTag");
         raw_msg_data
     }
 }
+impl From<UbusMsg> for Vec<u8> {
+    fn from(ubus_msg: UbusMsg) -> Self {
+        (&ubus_msg).into()
+    }
+}
 
 impl core::fmt::Debug for UbusMsg {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
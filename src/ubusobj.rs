@@ -2,11 +2,12 @@ extern crate alloc;
 use crate::*;
 use alloc::vec::Vec;
 use core::pin::Pin;
-use std::{boxed::Box, collections::HashMap, string::String, sync::Arc};
+use std::{boxed::Box, collections::HashMap, os::fd::OwnedFd, string::String, sync::Arc};
 
 type UbusMethodSync = Arc<dyn (Fn(MsgTable) -> MsgTable) + Send + Sync>;
 type UbusMethodAsync =
     Arc<dyn (Fn(MsgTable) -> Pin<Box<dyn Future<Output = MsgTable> + Send>>) + Send + Sync>;
+type UbusMethodWithRequest = Arc<dyn (Fn(UbusRequest) -> UbusReply) + Send + Sync>;
 // pub trait UbusMethodLike: Fn(&MsgTable) -> MsgTable + Send + Sync + 'static {}
 // impl<T> UbusMethodLike for T where T: Fn(&MsgTable) -> MsgTable + Send + Sync + 'static {}
 
@@ -14,6 +15,38 @@ type UbusMethodAsync =
 pub enum UbusMethod {
     Sync(UbusMethodSync),
     Async(UbusMethodAsync),
+    WithRequest(UbusMethodWithRequest),
+}
+
+/**
+ * everything a client sent in an INVOKE, used by `UbusServerObjectBuilder::method_with_request()`
+ */
+#[derive(Debug)]
+pub struct UbusRequest {
+    pub args: MsgTable,
+    /**
+     * fd passed by client along with the request, see `Connection::invoke_with_fd()`
+     */
+    pub fd: Option<OwnedFd>,
+    /**
+     * user and group of the client process, added by ubusd, check them with `UbusAclTable::lookup()`
+     */
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+/**
+ * reply of a method, an fd can be attached, it is passed to client along with the final STATUS
+ */
+#[derive(Debug, Default)]
+pub struct UbusReply {
+    pub data: MsgTable,
+    pub fd: Option<OwnedFd>,
+}
+impl From<MsgTable> for UbusReply {
+    fn from(data: MsgTable) -> Self {
+        Self { data, fd: None }
+    }
 }
 
 // #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -66,6 +99,20 @@ impl UbusServerObjectBuilder {
         self
     }
 
+    /**
+     * like `method()`, but the callback gets the whole `UbusRequest` (e.g. the fd passed by client),
+     * and can attach an fd to its `UbusReply`
+     */
+    pub fn method_with_request<M: Fn(UbusRequest) -> UbusReply + Send + Sync + 'static>(
+        mut self,
+        name: &str,
+        callback: M,
+    ) -> Self {
+        self.methods
+            .insert(name.into(), UbusMethod::WithRequest(Arc::new(callback)));
+        self
+    }
+
     // pub fn method_async<M: AsyncFn(MsgTable) -> MsgTable + Sync + Send + 'static>(
     //     mut self,
     //     name: &str,
@@ -116,17 +163,3 @@ impl std::fmt::Debug for UbusServerObject {
             .finish()
     }
 }
-
-/**
- * used in lookup
- */
-#[derive(Default, Debug, Clone)]
-pub struct UbusObject {
-    pub path: String,
-    pub id: HexU32,
-    pub objtype: HexU32,
-    /**
-     * used on client side lookup, store what the server says
-     */
-    pub reported_signature: MsgTable,
-}
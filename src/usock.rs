@@ -1,9 +1,13 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::UnixStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Interest};
 use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs, UnixStream};
 
 use super::*;
+use std::collections::VecDeque;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::path::Path;
+use std::vec::Vec;
 
 pub trait AsyncIoReader: Send + 'static {
     type Error: IOError;
@@ -11,6 +15,14 @@ pub trait AsyncIoReader: Send + 'static {
         &mut self,
         data: &mut [u8],
     ) -> impl std::future::Future<Output = Result<(), UbusError>> + Send;
+    /**
+     * take the fd passed along with the bytes got so far (ubusd sends it with the message header)
+     *
+     * only unix socket can pass fds, other transports never have one
+     */
+    fn take_fd(&mut self) -> Option<OwnedFd> {
+        None
+    }
 }
 
 pub trait AsyncIoWriter: Send + 'static {
@@ -19,9 +31,33 @@ pub trait AsyncIoWriter: Send + 'static {
         &mut self,
         data: &[u8],
     ) -> impl std::future::Future<Output = Result<(), UbusError>> + Send;
+    /**
+     * same as `put()`, but also pass an fd to the peer
+     *
+     * only unix socket can pass fds, other transports fail if `fd` is given
+     */
+    fn put_with_fd(
+        &mut self,
+        data: &[u8],
+        fd: Option<OwnedFd>,
+    ) -> impl std::future::Future<Output = Result<(), UbusError>> + Send {
+        async move {
+            if fd.is_some() {
+                return Err(UbusError::IO(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "this transport can't pass fd",
+                )));
+            }
+            self.put(data).await
+        }
+    }
 }
 
-impl AsyncIoReader for OwnedReadHalf {
+/**
+ * anything tokio can read from can be used as transport,
+ * e.g. halves of `UnixStream`, `TcpStream`, or `tokio::io::duplex()` in tests
+ */
+impl<T: AsyncRead + Unpin + Send + 'static> AsyncIoReader for T {
     type Error = std::io::Error;
     async fn get(&mut self, data: &mut [u8]) -> Result<(), UbusError> {
         self.read_exact(data)
@@ -30,24 +66,241 @@ impl AsyncIoReader for OwnedReadHalf {
             .and(Ok(()))
     }
 }
-impl AsyncIoWriter for OwnedWriteHalf {
+impl<T: AsyncWrite + Unpin + Send + 'static> AsyncIoWriter for T {
     type Error = std::io::Error;
     async fn put(&mut self, data: &[u8]) -> Result<(), UbusError> {
         self.write_all(data).await.map_err(UbusError::IO)
     }
 }
 
+/**
+ * read half of a unix socket, which also receives fds passed by `SCM_RIGHTS`
+ */
+pub struct UnixReader {
+    inner: OwnedReadHalf,
+    /* received but not yet taken, normally there is at most one */
+    fds: VecDeque<OwnedFd>,
+}
+
+/**
+ * write half of a unix socket, which can also pass fds by `SCM_RIGHTS`
+ */
+pub struct UnixWriter {
+    inner: OwnedWriteHalf,
+}
+
+/**
+ * split a unix socket into halves which support fd passing
+ */
+pub fn split_unix_stream(stream: UnixStream) -> (UnixReader, UnixWriter) {
+    let (inner_reader, inner_writer) = stream.into_split();
+    (
+        UnixReader {
+            inner: inner_reader,
+            fds: VecDeque::new(),
+        },
+        UnixWriter {
+            inner: inner_writer,
+        },
+    )
+}
+
+impl AsyncIoReader for UnixReader {
+    type Error = std::io::Error;
+    async fn get(&mut self, data: &mut [u8]) -> Result<(), UbusError> {
+        let socket = self.inner.as_ref();
+        let mut filled = 0;
+        while filled < data.len() {
+            let (len, fds) = socket
+                .async_io(Interest::READABLE, || {
+                    recv_with_fds(socket.as_raw_fd(), &mut data[filled..])
+                })
+                .await
+                .map_err(UbusError::IO)?;
+            if len == 0 {
+                return Err(UbusError::IO(io::ErrorKind::UnexpectedEof.into()));
+            }
+            filled += len;
+            self.fds.extend(fds);
+        }
+        Ok(())
+    }
+    fn take_fd(&mut self) -> Option<OwnedFd> {
+        self.fds.pop_front()
+    }
+}
+
+impl AsyncIoWriter for UnixWriter {
+    type Error = std::io::Error;
+    async fn put(&mut self, data: &[u8]) -> Result<(), UbusError> {
+        self.put_with_fd(data, None).await
+    }
+    async fn put_with_fd(&mut self, data: &[u8], fd: Option<OwnedFd>) -> Result<(), UbusError> {
+        let socket = self.inner.as_ref();
+        /* the fd goes with the first chunk, it is closed on our side once sent (kernel dups it for the peer) */
+        let mut fd = fd;
+        let mut sent = 0;
+        while sent < data.len() {
+            let len = socket
+                .async_io(Interest::WRITABLE, || {
+                    send_with_fd(
+                        socket.as_raw_fd(),
+                        &data[sent..],
+                        fd.as_ref().map(|fd| fd.as_raw_fd()),
+                    )
+                })
+                .await
+                .map_err(UbusError::IO)?;
+            fd = None;
+            sent += len;
+        }
+        Ok(())
+    }
+}
+
+/* how many fds can be received at once, libubus only sends one per message */
+const MAX_RECV_FDS: usize = 4;
+
+/* the control buffer of `msghdr`, `cmsghdr`s are read and written in place so it must be aligned like them */
+#[repr(C, align(8))]
+struct CmsgBuffer<const N: usize>([u8; N]);
+
+fn recv_with_fds(socket: RawFd, data: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>)> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let mut cmsg_buffer = CmsgBuffer(
+        [0u8; unsafe { libc::CMSG_SPACE((MAX_RECV_FDS * size_of::<RawFd>()) as u32) } as usize],
+    );
+    let mut msg: libc::msghdr = unsafe { core::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buffer.0.as_mut_ptr().cast();
+    msg.msg_controllen = cmsg_buffer.0.len() as _;
+
+    let len = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                let count =
+                    ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / size_of::<RawFd>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        log::warn!("too many fds passed at once, some are dropped");
+    }
+    Ok((len as usize, fds))
+}
+
+fn send_with_fd(socket: RawFd, data: &[u8], fd: Option<RawFd>) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut _,
+        iov_len: data.len(),
+    };
+    let mut cmsg_buffer =
+        CmsgBuffer([0u8; unsafe { libc::CMSG_SPACE(size_of::<RawFd>() as u32) } as usize]);
+    let mut msg: libc::msghdr = unsafe { core::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if let Some(fd) = fd {
+        msg.msg_control = cmsg_buffer.0.as_mut_ptr().cast();
+        msg.msg_controllen = cmsg_buffer.0.len() as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<RawFd>() as u32) as _;
+            libc::CMSG_DATA(cmsg).cast::<RawFd>().write_unaligned(fd);
+        }
+    }
+
+    let len = unsafe { libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(len as usize)
+}
+
 impl Connection {
+    /**
+     * Create a new ubus connection from a single bidirectional stream, it is split internally
+     */
+    pub async fn from_stream<S: AsyncRead + AsyncWrite + Send + 'static>(
+        stream: S,
+    ) -> Result<Self, UbusError> {
+        Self::new(tokio::io::split(stream)).await
+    }
+
+    /**
+     * connect to a unix socket, fd passing is supported
+     * a path starts with `@` (e.g. `@ubus`) is a socket in the abstract namespace
+     */
     pub async fn connect(path: &Path) -> Result<Self, UbusError> {
+        Self::new(split_unix_stream(Self::connect_unix_stream(path).await?)).await
+    }
+
+    /**
+     * connect to a ubusd forwarded over TCP, e.g. `socat TCP-LISTEN:12345,fork UNIX-CONNECT:/var/run/ubus/ubus.sock`
+     *
+     * ubusd doesn't know about TCP, this is intended for lab setups only
+     */
+    pub async fn connect_tcp<A: ToSocketAddrs>(addr: A) -> Result<Self, UbusError> {
         Self::new(
-            UnixStream::connect(path)
+            TcpStream::connect(addr)
                 .await
                 .map_err(UbusError::IO)?
                 .into_split(),
         )
         .await
     }
+
+    /**
+     * connect to the system ubusd
+     *
+     * the `UBUS_SOCKET` environment variable overrides the default socket path, it can be
+     *  - a path, e.g. `/tmp/ubus.sock`
+     *  - an abstract socket, e.g. `@ubus`
+     *  - a TCP address, e.g. `tcp://192.168.1.1:12345`
+     */
     pub async fn connect_ubusd() -> Result<Self, UbusError> {
-        Self::connect(Path::new("/var/run/ubus/ubus.sock")).await
+        match std::env::var("UBUS_SOCKET") {
+            Ok(socket) if !socket.is_empty() => match socket.strip_prefix("tcp://") {
+                Some(addr) => Self::connect_tcp(addr).await,
+                None => Self::connect(Path::new(&socket)).await,
+            },
+            _ => Self::connect(Path::new(UBUS_DEFAULT_SOCKET)).await,
+        }
+    }
+
+    async fn connect_unix_stream(path: &Path) -> Result<UnixStream, UbusError> {
+        use std::os::unix::ffi::OsStrExt;
+
+        match path.as_os_str().as_bytes().strip_prefix(b"@") {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::{SocketAddr, UnixStream as StdUnixStream};
+
+                /* connecting a unix socket doesn't block for long, so it's fine to do it synchronously */
+                let addr = SocketAddr::from_abstract_name(name).map_err(UbusError::IO)?;
+                let stream = StdUnixStream::connect_addr(&addr).map_err(UbusError::IO)?;
+                stream.set_nonblocking(true).map_err(UbusError::IO)?;
+                UnixStream::from_std(stream).map_err(UbusError::IO)
+            }
+            _ => UnixStream::connect(path).await.map_err(UbusError::IO),
+        }
     }
 }
@@ -60,9 +60,12 @@ macro_rules! values {
     };
 }
 
+/**
+ * malformed data comes from peers, it must never panic, only leave a trace for debugging
+ */
 #[macro_export]
 macro_rules! invalid_data_panic {
-    ($($arg:tt)*) => (if cfg!(debug_assertions) { panic!($($arg)*); })
+    ($($arg:tt)*) => (log::trace!($($arg)*))
 }
 
 #[macro_export]
@@ -70,19 +73,19 @@ macro_rules! valid_data {
     (($left:expr) >= ($right:expr), $msg:literal) => {{
         if !(($left) >= ($right)) {
             $crate::invalid_data_panic!("Invalid Data: {} ({:?} < {:?})", $msg, $left, $right);
-            return Err(Error::InvalidData($msg));
+            return Err($crate::UbusError::InvalidData($msg));
         }
     }};
     (($left:expr) == ($right:expr), $msg:literal) => {{
         if !(($left) == ($right)) {
             $crate::invalid_data_panic!("Invalid Data: {} ({:?} != {:?})", $msg, $left, $right);
-            return Err(Error::InvalidData($msg));
+            return Err($crate::UbusError::InvalidData($msg));
         }
     }};
     ($thing:expr, $msg:literal) => {{
         if !($thing) {
             $crate::invalid_data_panic!("Invalid Data: {}", $msg);
-            return Err(UbusError::InvalidData($msg));
+            return Err($crate::UbusError::InvalidData($msg));
         }
     }};
 }
@@ -0,0 +1,94 @@
+#![cfg(feature = "tokio")]
+
+use serde_json::json;
+use tokio::net::UnixStream;
+use ubus::*;
+
+mod common;
+use common::{TEST_HELLO, reply};
+
+#[tokio::test]
+async fn test_query_acl() {
+    let (client, server) = UnixStream::pair().unwrap();
+
+    tokio::spawn(async move {
+        let (mut reader, mut writer) = server.into_split();
+        writer.put(TEST_HELLO).await.unwrap();
+
+        let request = UbusMsg::from_io(&mut reader).await.unwrap();
+        assert_eq!(request.header.cmd_type, UbusCmdType::INVOKE);
+        assert_eq!(request.get_attr_obj_id(), Some(UBUS_SYSTEM_OBJECT_ACL));
+
+        let data = json!({
+            "seq": 3,
+            "acl": [
+                {"obj": "myobj", "user": "network", "acl": {"level": "read"}},
+                {"obj": "myobj", "group": "admin", "acl": {"level": "write"}},
+            ]
+        });
+        writer
+            .put(&reply(
+                &request,
+                UbusCmdType::DATA,
+                vec![UbusBlob::Data(data.try_into().unwrap())],
+            ))
+            .await
+            .unwrap();
+        writer
+            .put(&reply(
+                &request,
+                UbusCmdType::STATUS,
+                vec![UbusBlob::Status(UbusMsgStatus::OK)],
+            ))
+            .await
+            .unwrap();
+    });
+
+    let connection = Connection::new(client.into_split()).await.unwrap();
+    let acl = connection.query_acl().await.unwrap();
+    assert_eq!(acl.sequence, 3);
+    assert_eq!(acl.entries.len(), 2);
+
+    let allowed: Vec<_> = acl
+        .lookup("myobj", Some("network"), Some("users"))
+        .collect();
+    assert_eq!(allowed.len(), 1);
+    assert_eq!(
+        allowed[0].acl.to_string_clone().unwrap(),
+        r#"{"level":"read"}"#
+    );
+    assert_eq!(acl.lookup("myobj", Some("root"), Some("admin")).count(), 1);
+    assert_eq!(acl.lookup("other", Some("network"), None).count(), 0);
+}
+
+#[tokio::test]
+async fn test_permission_denied() {
+    let (client, server) = UnixStream::pair().unwrap();
+
+    tokio::spawn(async move {
+        let (mut reader, mut writer) = server.into_split();
+        writer.put(TEST_HELLO).await.unwrap();
+
+        let request = UbusMsg::from_io(&mut reader).await.unwrap();
+        writer
+            .put(&reply(
+                &request,
+                UbusCmdType::STATUS,
+                vec![UbusBlob::Status(UbusMsgStatus::PERMISSION_DENIED)],
+            ))
+            .await
+            .unwrap();
+    });
+
+    let connection = Connection::new(client.into_split()).await.unwrap();
+    match connection
+        .invoke(0x13333337.into(), "info", MsgTable::new())
+        .await
+    {
+        Err(UbusError::PermissionDenied { object, method }) => {
+            assert_eq!(object, "13333337");
+            assert_eq!(method, "info");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
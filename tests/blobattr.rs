@@ -0,0 +1,91 @@
+use ubus::*;
+
+/* like `enum { INSTANCE_ATTR_COMMAND, INSTANCE_ATTR_PID, INSTANCE_ATTR_ENV }` in procd */
+static ENV: [BlobAttrInfo; 1] = [BlobAttrInfo::new(BlobAttrType::STRING)];
+static INSTANCE: [BlobAttrInfo; 3] = [
+    BlobAttrInfo::new(BlobAttrType::STRING).min_len(2),
+    BlobAttrInfo::new(BlobAttrType::INT32),
+    BlobAttrInfo::nested(&ENV),
+];
+
+#[test]
+fn test_blob_attr_roundtrip() {
+    let attrs = vec![
+        BlobAttr::new(0, BlobAttrPayload::String("/sbin/netifd".into())),
+        BlobAttr::new(1, BlobAttrPayload::Int32(1234)),
+        BlobAttr::new(
+            2,
+            BlobAttrPayload::Nested(vec![
+                BlobAttr::new(0, BlobAttrPayload::String("PATH=/usr/bin".into())),
+                BlobAttr::new(0, BlobAttrPayload::String("HOME=/".into())),
+            ]),
+        ),
+        /* unknown to the table, kept as is */
+        BlobAttr::new(9, BlobAttrPayload::Binary(vec![1, 2, 3])),
+    ];
+    let mut builder = BlobBuilder::new();
+    builder.push_blob_attrs(&attrs).unwrap();
+    assert_eq!(
+        &builder.as_slice()[..20],
+        b"\x00\x00\x00\x11/sbin/netifd\x00\x00\x00\x00"
+    );
+    assert_eq!(
+        &builder.as_slice()[20..28],
+        b"\x01\x00\x00\x08\x00\x00\x04\xd2"
+    );
+    assert_eq!(
+        BlobAttr::parse_all(builder.as_slice(), &INSTANCE).unwrap(),
+        attrs
+    );
+}
+
+#[test]
+fn test_blob_attr_invalid() {
+    /* INT32 with 2 bytes */
+    let data = b"\x01\x00\x00\x06\x00\x01\x00\x00";
+    assert!(matches!(
+        BlobAttr::parse_all(data, &INSTANCE),
+        Err(UbusError::InvalidBlob {
+            offset: 0,
+            blob_type: 1,
+            ..
+        })
+    ));
+    /* shorter than min_len, then a valid pid */
+    let data = b"\x00\x00\x00\x05\x00\x00\x00\x00\x01\x00\x00\x08\x00\x00\x00\x2a";
+    assert!(BlobAttr::parse_all(data, &INSTANCE).is_err());
+    let options = ParseOptions::default().lenient(true);
+    assert_eq!(
+        BlobAttr::parse_all_with_options(data, &INSTANCE, options).unwrap(),
+        vec![BlobAttr::new(1, BlobAttrPayload::Int32(42))]
+    );
+    /* blobmsgs don't belong here */
+    let blobmsg: Vec<u8> = MsgTable::try_from(r#"{"a": 1}"#)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert!(BlobAttr::parse_all(&blobmsg, &INSTANCE).is_err());
+}
+
+#[test]
+fn test_blob_attr_non_utf8_string() {
+    /* a command line in latin-1 */
+    let data = b"\x00\x00\x00\x0b/bin/\xe9\x00\x00";
+    assert!(BlobAttr::parse_all(data, &INSTANCE).is_err());
+    let options = ParseOptions::default().utf8(Utf8Policy::Bytes);
+    let attrs = BlobAttr::parse_all_with_options(data, &INSTANCE, options).unwrap();
+    assert_eq!(
+        attrs,
+        vec![BlobAttr::new(
+            0,
+            BlobAttrPayload::Binary(b"/bin/\xe9\x00".to_vec())
+        )]
+    );
+    let mut builder = BlobBuilder::new();
+    builder.push_blob_attrs(&attrs).unwrap();
+    assert_eq!(builder.as_slice(), &data[..]);
+    assert_eq!(
+        BlobAttr::parse_all_with_options(builder.as_slice(), &INSTANCE, options).unwrap(),
+        attrs
+    );
+}
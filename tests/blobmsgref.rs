@@ -0,0 +1,117 @@
+use serde_json::json;
+use ubus::*;
+
+mod common;
+
+fn table_bytes() -> Vec<u8> {
+    let table: MsgTable = json!({
+        "up": true,
+        "mtu": 1500,
+        "name": "br-lan",
+        "statistics": {"rx_bytes": 5000000000i64, "tx_bytes": 12},
+        "ports": ["lan1", "lan2"],
+    })
+    .try_into()
+    .unwrap();
+    table.try_into().unwrap()
+}
+
+#[test]
+fn test_msgtable_ref_walk() {
+    let bytes = table_bytes();
+    let table = MsgTableRef::new(&bytes);
+
+    let names: Vec<&str> = table.iter().map(|blobmsg| blobmsg.unwrap().name).collect();
+    assert_eq!(names.len(), 5);
+    assert!(names.contains(&"statistics"));
+
+    assert!(matches!(
+        table.get("up"),
+        Some(BlobMsgPayloadRef::Bool(true))
+    ));
+    assert!(matches!(
+        table.get("mtu"),
+        Some(BlobMsgPayloadRef::Int16(1500))
+    ));
+    assert!(matches!(
+        table.get("name"),
+        Some(BlobMsgPayloadRef::String("br-lan"))
+    ));
+    assert!(table.get("missing").is_none());
+
+    let Some(BlobMsgPayloadRef::Table(statistics)) = table.get("statistics") else {
+        panic!("statistics is not a table");
+    };
+    assert!(matches!(
+        statistics.get("rx_bytes"),
+        Some(BlobMsgPayloadRef::Int64(5000000000))
+    ));
+
+    let Some(BlobMsgPayloadRef::Array(ports)) = table.get("ports") else {
+        panic!("ports is not an array");
+    };
+    let ports: Vec<&str> = ports
+        .iter()
+        .map(|port| match port.unwrap().data {
+            BlobMsgPayloadRef::String(s) => s,
+            other => panic!("unexpected port {:?}", other),
+        })
+        .collect();
+    assert_eq!(ports, ["lan1", "lan2"]);
+}
+
+#[test]
+fn test_msgtable_ref_to_owned() {
+    let bytes = table_bytes();
+    let owned = MsgTableRef::new(&bytes).to_msg_table().unwrap();
+    assert_eq!(Vec::<u8>::try_from(owned).unwrap(), bytes);
+}
+
+#[test]
+fn test_msgtable_ref_broken() {
+    let mut bytes = table_bytes();
+    /* claim the first blob is much longer than it is */
+    bytes[1] = 0xff;
+    let mut iter = MsgTableRef::new(&bytes).iter();
+    assert!(matches!(
+        iter.next(),
+        Some(Err(UbusError::InvalidBlob { offset: 0, .. }))
+    ));
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_msgtable_ref_broken_nested() {
+    let table: MsgTable = json!({"a": {"b": "x"}}).try_into().unwrap();
+    let mut bytes: Vec<u8> = table.try_into().unwrap();
+    /* "a" takes a tag, a name length and a padded name, "b" starts right after */
+    bytes[9] = 0xff;
+    assert!(matches!(
+        MsgTableRef::new(&bytes).to_msg_table(),
+        Err(UbusError::InvalidBlob { offset: 8, .. })
+    ));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_read_raw_reuses_buffer() {
+    let data: MsgTable = json!({"name": "br-lan"}).try_into().unwrap();
+    let message = common::message(UbusCmdType::DATA, 1, 0x1234, vec![UbusBlob::Data(data)]);
+
+    let mut stream = Vec::new();
+    stream.extend_from_slice(&message);
+    stream.extend_from_slice(&message);
+    let mut reader = std::io::Cursor::new(stream);
+
+    let mut buffer = Vec::new();
+    for _ in 0..2 {
+        let raw = UbusMsg::read_raw(&mut reader, &mut buffer).await.unwrap();
+        assert_eq!(raw.header.cmd_type, UbusCmdType::DATA);
+        assert!(matches!(
+            raw.data().unwrap().get("name"),
+            Some(BlobMsgPayloadRef::String("br-lan"))
+        ));
+        let owned = UbusMsg::try_from(raw).unwrap();
+        assert!(matches!(owned.ubus_blobs[..], [UbusBlob::Data(_)]));
+    }
+}
@@ -0,0 +1,229 @@
+#![cfg(all(feature = "std", unix))]
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+use ubus::*;
+
+mod common;
+use common::message;
+
+fn send(stream: &mut UnixStream, cmd_type: UbusCmdType, sequence: u16, ubus_blobs: Vec<UbusBlob>) {
+    stream
+        .write_all(&message(cmd_type, sequence, 0x1234, ubus_blobs))
+        .unwrap();
+}
+
+/* `None` once the client is gone */
+fn recv(stream: &mut UnixStream) -> Option<UbusMsg> {
+    let mut data = vec![0; 12];
+    stream.read_exact(&mut data).ok()?;
+    let size = BlobTag::from_bytes(data[8..12].try_into().unwrap()).size();
+    data.resize(8 + size, 0);
+    stream.read_exact(&mut data[12..]).unwrap();
+    Some(UbusMsg::try_from(UbusMsgRef::from_bytes(&data).unwrap()).unwrap())
+}
+
+fn method_of(message: &UbusMsg) -> Option<&str> {
+    message
+        .ubus_blobs
+        .iter()
+        .find_map(|ubus_blob| match ubus_blob {
+            UbusBlob::Method(method) => Some(method.as_str()),
+            _ => None,
+        })
+}
+
+/* a ubusd serving one client, `serve` gets each request and replies to it */
+fn fake_ubusd(
+    mut serve: impl FnMut(&mut UnixStream, UbusMsg) + Send + 'static,
+) -> (blocking::Connection, thread::JoinHandle<()>) {
+    let (client, mut server) = UnixStream::pair().unwrap();
+    let ubusd = thread::spawn(move || {
+        send(&mut server, UbusCmdType::HELLO, 0, vec![]);
+        while let Some(message) = recv(&mut server) {
+            serve(&mut server, message);
+        }
+    });
+    (blocking::Connection::new(client).unwrap(), ubusd)
+}
+
+#[test]
+fn test_blocking_call() {
+    let (mut conn, ubusd) = fake_ubusd(|server, message| {
+        let sequence = u16::from(message.header.sequence);
+        match message.header.cmd_type {
+            UbusCmdType::LOOKUP => {
+                send(
+                    server,
+                    UbusCmdType::DATA,
+                    sequence,
+                    vec![
+                        UbusBlob::ObjPath("test".into()),
+                        UbusBlob::ObjId(0xabcd.into()),
+                    ],
+                );
+                send(server, UbusCmdType::STATUS, sequence, vec![]);
+            }
+            UbusCmdType::INVOKE if method_of(&message) == Some("hello") => {
+                /* an event in the middle of a reply is kept for later */
+                send(
+                    server,
+                    UbusCmdType::INVOKE,
+                    9,
+                    vec![
+                        UbusBlob::ObjId(0x42.into()),
+                        UbusBlob::Method("test.event".into()),
+                        UbusBlob::Data(msgtable! { "up": true }),
+                    ],
+                );
+                send(
+                    server,
+                    UbusCmdType::DATA,
+                    sequence,
+                    vec![
+                        UbusBlob::ObjId(0xabcd.into()),
+                        UbusBlob::Data(msgtable! { "echo": "hi" }),
+                    ],
+                );
+                send(
+                    server,
+                    UbusCmdType::STATUS,
+                    sequence,
+                    vec![UbusBlob::Status(UbusMsgStatus::OK)],
+                );
+            }
+            UbusCmdType::INVOKE => send(
+                server,
+                UbusCmdType::STATUS,
+                sequence,
+                vec![UbusBlob::Status(UbusMsgStatus::PERMISSION_DENIED)],
+            ),
+            UbusCmdType::STATUS => assert_eq!(u16::from(message.header.sequence), 9),
+            _ => panic!("unexpected {:?}", message),
+        }
+    });
+    assert_eq!(u32::from(conn.client_id()), 0x1234);
+
+    let reply = conn
+        .call("test", "hello", msgtable! { "msg": "hi" })
+        .unwrap();
+    assert_eq!(reply.get_str("echo"), Some("hi"));
+    assert!(matches!(
+        conn.call("test", "secret", MsgTable::new()),
+        Err(UbusError::PermissionDenied { object, .. }) if object == "test"
+    ));
+
+    let event = conn.next_invoke(Some(Duration::ZERO)).unwrap().unwrap();
+    assert_eq!(event.method, "test.event");
+    assert_eq!(event.args.get_bool("up"), Some(true));
+    conn.reply_status(&event, UbusMsgStatus::OK).unwrap();
+    assert!(
+        conn.next_invoke(Some(Duration::from_millis(10)))
+            .unwrap()
+            .is_none()
+    );
+    drop(conn);
+    ubusd.join().unwrap();
+}
+
+#[test]
+fn test_blocking_listen() {
+    let (mut conn, ubusd) = fake_ubusd(|server, message| {
+        let sequence = u16::from(message.header.sequence);
+        match message.header.cmd_type {
+            UbusCmdType::ADD_OBJECT => {
+                /* anonymous */
+                assert!(
+                    !message
+                        .ubus_blobs
+                        .iter()
+                        .any(|ubus_blob| matches!(ubus_blob, UbusBlob::ObjPath(_)))
+                );
+                send(
+                    server,
+                    UbusCmdType::DATA,
+                    sequence,
+                    vec![UbusBlob::ObjId(0x42.into()), UbusBlob::ObjType(0x43.into())],
+                );
+                send(server, UbusCmdType::STATUS, sequence, vec![]);
+            }
+            UbusCmdType::INVOKE => {
+                assert_eq!(message.get_attr_obj_id(), Some(UBUS_SYSTEM_OBJECT_EVENT));
+                assert_eq!(method_of(&message), Some("register"));
+                send(
+                    server,
+                    UbusCmdType::STATUS,
+                    sequence,
+                    vec![UbusBlob::Status(UbusMsgStatus::OK)],
+                );
+                send(
+                    server,
+                    UbusCmdType::INVOKE,
+                    1,
+                    vec![
+                        UbusBlob::ObjId(0x42.into()),
+                        UbusBlob::Method("network.interface".into()),
+                        UbusBlob::Data(msgtable! { "action": "ifup" }),
+                        UbusBlob::NoReply(true),
+                    ],
+                );
+            }
+            _ => panic!("unexpected {:?}", message),
+        }
+    });
+    let listener = conn.listen("network.*").unwrap();
+    assert_eq!(u32::from(listener), 0x42);
+    let event = conn.next_invoke(None).unwrap().unwrap();
+    assert_eq!(u32::from(event.obj_id), 0x42);
+    assert_eq!(event.method, "network.interface");
+    assert_eq!(event.args.get_str("action"), Some("ifup"));
+    assert!(event.no_reply);
+    drop(conn);
+    ubusd.join().unwrap();
+}
+
+#[test]
+fn test_blocking_number_encoding() {
+    let (mut conn, ubusd) = fake_ubusd(|server, message| {
+        let sequence = u16::from(message.header.sequence);
+        let args = message
+            .ubus_blobs
+            .iter()
+            .find_map(|ubus_blob| match ubus_blob {
+                UbusBlob::Data(args) => Some(args.clone()),
+                _ => None,
+            })
+            .unwrap();
+        send(
+            server,
+            UbusCmdType::DATA,
+            sequence,
+            vec![UbusBlob::Data(args)],
+        );
+        send(server, UbusCmdType::STATUS, sequence, vec![]);
+    });
+    conn.set_number_encoding(NumberEncoding::Int64);
+    /* explicit widths are kept */
+    let echo = conn
+        .invoke(1.into(), "echo", msgtable! { "x": 10i16 })
+        .unwrap();
+    assert!(matches!(echo.0[0].data, BlobMsgPayload::Int16(10)));
+    /* JSON has no widths, it's up to the connection */
+    let echo = conn.invoke_json(1.into(), "echo", r#"{"x": 10}"#).unwrap();
+    assert!(matches!(echo.0[0].data, BlobMsgPayload::Int64(10)));
+    drop(conn);
+    ubusd.join().unwrap();
+}
+
+#[test]
+fn test_blocking_send_failure() {
+    let (client, mut server) = UnixStream::pair().unwrap();
+    send(&mut server, UbusCmdType::HELLO, 0, vec![]);
+    let mut conn = blocking::Connection::new(client).unwrap();
+    /* ubusd is gone before the request is sent */
+    drop(server);
+    assert!(matches!(conn.ping(), Err(UbusError::IO(_))));
+    assert_eq!(conn.pending_count(), 0);
+}
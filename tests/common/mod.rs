@@ -0,0 +1,51 @@
+/* what a fake ubusd says, shared by the tests talking to `Connection` */
+#![allow(dead_code)]
+
+use ubus::*;
+
+/* a message as it is on wire */
+pub fn message(
+    cmd_type: UbusCmdType,
+    sequence: u16,
+    peer: u32,
+    ubus_blobs: Vec<UbusBlob>,
+) -> Vec<u8> {
+    let header = UbusMsgHeader {
+        version: UbusMsgVersion::CURRENT,
+        cmd_type,
+        sequence: sequence.into(),
+        peer: peer.into(),
+    };
+    UbusMsg::from_header_and_blobs(&header, ubus_blobs).to_bytes()
+}
+
+/* answer `request` in its session */
+pub fn reply(request: &UbusMsg, cmd_type: UbusCmdType, ubus_blobs: Vec<UbusBlob>) -> Vec<u8> {
+    message(
+        cmd_type,
+        request.header.sequence.into(),
+        request.header.peer.into(),
+        ubus_blobs,
+    )
+}
+
+/* HELLO, the client_id is 0x2eb863db */
+pub const TEST_HELLO: &[u8] = &[
+    0x00, 0x00, 0x00, 0x00, 0x2e, 0xb8, 0x63, 0xdb, 0x00, 0x00, 0x00, 0x04,
+];
+
+/* the first request of a connection is a PING with seq 1 */
+pub const PING_TX: &[u8] = &[
+    0x00, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
+];
+
+/* ubusd answers a PING with an empty DATA and a STATUS */
+pub const PING_RX: &[&[u8]] = &[
+    &[
+        0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
+    ],
+    &[
+        0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x01, 0x00, 0x00,
+        0x08, 0x00, 0x00, 0x00, 0x00,
+    ],
+];
@@ -65,7 +65,7 @@ mod tests {
         stream.extend_from_slice(&b2.to_bytes_clone());
 
         let mut iter = BlobIter::new(&stream);
-        let first = iter.next().expect("first blob");
+        let first = iter.next().expect("first blob").expect("valid blob");
         match first {
             Blob::UbusBlob(UbusBlob::ObjId(v)) => {
                 // BlobBuilder wrote a u32 payload; UbusBlob parses into i32 -> compare by bit pattern
@@ -74,7 +74,7 @@ mod tests {
             other => panic!("unexpected first variant: {:?}", other),
         }
 
-        let second = iter.next().expect("second blob");
+        let second = iter.next().expect("second blob").expect("valid blob");
         match second {
             Blob::UbusBlob(UbusBlob::ObjPath(s)) => {
                 assert_eq!(s, path);
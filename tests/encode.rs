@@ -0,0 +1,313 @@
+use serde_json::json;
+use ubus::*;
+
+fn sample_table() -> MsgTable {
+    json!({
+        "name": "br-lan",
+        "up": true,
+        "mtu": 1500,
+        "speed": 2500000000i64,
+        "statistics": {"rx_bytes": 1024, "ratio": 0.5},
+        "ports": ["lan1", "lan2", ["nested"]],
+        "empty": {},
+    })
+    .try_into()
+    .unwrap()
+}
+
+#[test]
+fn test_nested_builder_matches_blobmsg_builder() {
+    let table = sample_table();
+
+    /* the old way, every BlobMsg is encoded into its own buffer and copied into the parent */
+    let mut expected = Vec::new();
+    for blobmsg in table.0.iter().cloned() {
+        expected.extend_from_slice(BlobMsgBuilder::try_from(blobmsg).unwrap().data_as_slice());
+    }
+
+    let mut builder = BlobBuilder::new();
+    builder.push_msg_table(&table.0).unwrap();
+    assert_eq!(builder.as_slice(), &expected[..]);
+
+    let parsed: MsgTable = MsgTableRef::new(builder.as_slice()).to_msg_table().unwrap();
+    assert_eq!(
+        parsed.to_string().unwrap(),
+        sample_table().to_string().unwrap()
+    );
+}
+
+#[test]
+fn test_open_close_by_hand() {
+    let mut builder = BlobBuilder::new();
+    let data = builder.open_nest(UbusBlobType::DATA.value()).unwrap();
+    let statistics = builder.open_table("statistics").unwrap();
+    builder
+        .push_blobmsg_payload("rx_bytes", &BlobMsgPayload::Int32(1024))
+        .unwrap();
+    let ports = builder.open_array("ports").unwrap();
+    builder
+        .push_blobmsg_payload("", &BlobMsgPayload::String("lan1".into()))
+        .unwrap();
+    builder.close(ports).unwrap();
+    builder.close(statistics).unwrap();
+    builder.close(data).unwrap();
+
+    let blobmsg = |name: &str, data| BlobMsg {
+        name: name.into(),
+        data,
+    };
+    let expected = UbusBlob::Data(MsgTable(vec![blobmsg(
+        "statistics",
+        BlobMsgPayload::Table(vec![
+            blobmsg("rx_bytes", BlobMsgPayload::Int32(1024)),
+            blobmsg(
+                "ports",
+                BlobMsgPayload::Array(vec![blobmsg("", BlobMsgPayload::String("lan1".into()))]),
+            ),
+        ]),
+    )]))
+    .to_bytes();
+    assert_eq!(builder.to_bytes(), expected);
+}
+
+#[test]
+fn test_encode_into_reuses_buffer() {
+    let message = UbusMsg {
+        header: UbusMsgHeader {
+            version: UbusMsgVersion::CURRENT,
+            cmd_type: UbusCmdType::INVOKE,
+            sequence: 7.into(),
+            peer: 0x1234.into(),
+        },
+        ubus_blobs: vec![
+            UbusBlob::ObjId(0x1234.into()),
+            UbusBlob::Method("status".into()),
+            UbusBlob::Data(sample_table()),
+        ],
+    };
+
+    let mut buffer = Vec::new();
+    message.encode_into(&mut buffer).unwrap();
+    let capacity = buffer.capacity();
+    buffer.clear();
+    message.encode_into(&mut buffer).unwrap();
+    assert_eq!(buffer.capacity(), capacity);
+    assert_eq!(buffer, message.clone().to_bytes());
+
+    let container = BlobTag::from_bytes(
+        buffer[UbusMsgHeader::SIZE..UbusMsgHeader::SIZE + BlobTag::SIZE]
+            .try_into()
+            .unwrap(),
+    );
+    assert_eq!(container.size(), buffer.len() - UbusMsgHeader::SIZE);
+
+    /* appended like `MsgTable::encode_into()` */
+    let single = buffer.clone();
+    message.encode_into(&mut buffer).unwrap();
+    assert_eq!(buffer, [single.clone(), single].concat());
+}
+
+#[test]
+fn test_encode_by_reference() {
+    let table = sample_table();
+    let owned = Vec::<u8>::try_from(sample_table()).unwrap();
+
+    let mut buffer = Vec::new();
+    table.encode_into(&mut buffer).unwrap();
+    assert_eq!(buffer, owned);
+    assert_eq!(Vec::<u8>::try_from(&table).unwrap(), owned);
+
+    let message = UbusMsg {
+        header: UbusMsgHeader {
+            version: UbusMsgVersion::CURRENT,
+            cmd_type: UbusCmdType::NOTIFY,
+            sequence: 1.into(),
+            peer: 0x1234.into(),
+        },
+        ubus_blobs: vec![UbusBlob::Data(table)],
+    };
+    /* the message is still usable after encoding */
+    assert_eq!(Vec::<u8>::from(&message), message.clone().to_bytes());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_encode_into_bytes_mut() {
+    let message = UbusMsg {
+        header: UbusMsgHeader {
+            version: UbusMsgVersion::CURRENT,
+            cmd_type: UbusCmdType::NOTIFY,
+            sequence: 1.into(),
+            peer: 0x1234.into(),
+        },
+        ubus_blobs: vec![UbusBlob::Data(sample_table())],
+    };
+
+    let mut buffer = bytes::BytesMut::new();
+    message.encode_into(&mut buffer).unwrap();
+    assert_eq!(&buffer[..], &Vec::<u8>::from(&message)[..]);
+}
+
+#[test]
+fn test_null_roundtrip() {
+    let table = MsgTable::try_from(r#"{"a":null,"b":[1,null]}"#).unwrap();
+    assert!(matches!(table.0[0].data, BlobMsgPayload::Null));
+
+    let bytes = Vec::<u8>::try_from(&table).unwrap();
+    /* libblobmsg encodes null as UNSPEC without payload: tag + namelen + "a\0" */
+    let tag = BlobTag::from_bytes(bytes[..BlobTag::SIZE].try_into().unwrap());
+    assert_eq!(tag.blob_type(), BlobMsgType::UNSPEC.value());
+    assert_eq!(tag.size(), BlobTag::SIZE + 4);
+
+    let parsed = MsgTableRef::new(&bytes).to_msg_table().unwrap();
+    assert_eq!(parsed.to_string().unwrap(), r#"{"a":null,"b":[1,null]}"#);
+    assert!(matches!(
+        MsgTableRef::new(&bytes).get("a"),
+        Some(BlobMsgPayloadRef::Null)
+    ));
+
+    /* the old builder doesn't panic either */
+    assert_eq!(
+        BlobMsgBuilder::try_from(table.0[0].clone()).unwrap().data(),
+        bytes[..tag.next_tag()]
+    );
+}
+
+#[test]
+fn test_unknown_payload_reencoded() {
+    let blobmsg = BlobMsg {
+        name: "future".into(),
+        data: BlobMsgPayload::Unknown(0x20, vec![1, 2, 3, 4, 5]),
+    };
+    let bytes = Vec::<u8>::try_from(blobmsg.clone()).unwrap();
+    let parsed = BlobMsg::try_from(&bytes[..]).unwrap();
+    assert!(matches!(
+        &parsed.data,
+        BlobMsgPayload::Unknown(0x20, raw) if raw == &[1, 2, 3, 4, 5]
+    ));
+    assert_eq!(Vec::<u8>::try_from(parsed).unwrap(), bytes);
+    assert_eq!(BlobMsgBuilder::try_from(blobmsg).unwrap().data(), bytes);
+}
+
+#[test]
+fn test_unknown_payload_out_of_range() {
+    let unknown = BlobMsg {
+        name: "future".into(),
+        data: BlobMsgPayload::Unknown(0x80, vec![1]),
+    };
+    assert!(BlobMsgBuilder::try_from(unknown.clone()).is_err());
+    /* nested ones fail the same, not panic */
+    let nested = BlobMsg {
+        name: "list".into(),
+        data: BlobMsgPayload::Array(vec![unknown.clone()]),
+    };
+    assert!(BlobMsgBuilder::try_from(nested).is_err());
+    let nested = BlobMsg {
+        name: "table".into(),
+        data: BlobMsgPayload::Table(vec![unknown]),
+    };
+    assert!(BlobMsgBuilder::try_from(nested).is_err());
+}
+
+#[test]
+fn test_int8_vs_bool() {
+    let counter = BlobMsg {
+        name: "counter".into(),
+        data: BlobMsgPayload::Int8(5),
+    };
+    let bytes = Vec::<u8>::try_from(counter).unwrap();
+
+    /* libblobmsg_json reads type 7 as bool */
+    let parsed = BlobMsg::try_from(&bytes[..]).unwrap();
+    assert!(matches!(parsed.data, BlobMsgPayload::Bool(true)));
+
+    let options = ParseOptions::default().int8_as_bool(false);
+    let parsed = BlobMsg::from_bytes_with_options(&bytes, options).unwrap();
+    assert!(matches!(parsed.data, BlobMsgPayload::Int8(5)));
+    assert_eq!(Vec::<u8>::try_from(parsed).unwrap(), bytes);
+
+    /* borrowed views follow the options into nested tables */
+    let table = MsgTable::from(vec![BlobMsg {
+        name: "stats".into(),
+        data: BlobMsgPayload::Table(vec![BlobMsg {
+            name: "counter".into(),
+            data: BlobMsgPayload::Int8(-3),
+        }]),
+    }]);
+    let bytes = Vec::<u8>::try_from(&table).unwrap();
+    let Some(BlobMsgPayloadRef::Table(stats)) =
+        MsgTableRef::with_options(&bytes, options).get("stats")
+    else {
+        panic!("stats table not found");
+    };
+    assert!(matches!(
+        stats.get("counter"),
+        Some(BlobMsgPayloadRef::Int8(-3))
+    ));
+    assert_eq!(
+        MsgTableRef::with_options(&bytes, options)
+            .to_msg_table()
+            .unwrap()
+            .to_string()
+            .unwrap(),
+        r#"{"stats":{"counter":-3}}"#
+    );
+}
+
+#[test]
+fn test_u64_keeps_bits() {
+    let table = MsgTable::try_from(r#"{"big":18446744073709551615,"mid":4294967295}"#).unwrap();
+    assert!(matches!(table.0[0].data, BlobMsgPayload::Int64(-1)));
+    assert_eq!(table.0[0].data.as_u64(), Some(u64::MAX));
+    assert_eq!(table.0[1].data.as_u64(), Some(u32::MAX as u64));
+
+    /* `blobmsg_add_u32()` of u32::MAX, read back with the width of INT32 */
+    assert_eq!(BlobMsgPayload::Int32(-1).as_u64(), Some(u32::MAX as u64));
+    assert_eq!(BlobMsgPayload::Int32(-1).as_i64(), Some(-1));
+    assert_eq!(BlobMsgPayload::String("1".into()).as_u64(), None);
+}
+
+#[test]
+fn test_number_encoding() {
+    let json = r#"{"small":1,"mid":100000,"big":5000000000,"list":[2]}"#;
+    let types = |table: &MsgTable| -> Vec<u32> {
+        let bytes = Vec::<u8>::try_from(table).unwrap();
+        let mut types: Vec<u32> = MsgTableRef::new(&bytes)
+            .iter()
+            .map(|blobmsg| match blobmsg.unwrap().data {
+                BlobMsgPayloadRef::Int16(_) => 16,
+                BlobMsgPayloadRef::Int32(_) => 32,
+                BlobMsgPayloadRef::Int64(_) => 64,
+                BlobMsgPayloadRef::Array(list) => match list.get("") {
+                    Some(BlobMsgPayloadRef::Int16(_)) => 16,
+                    Some(BlobMsgPayloadRef::Int32(_)) => 32,
+                    Some(BlobMsgPayloadRef::Int64(_)) => 64,
+                    _ => 0,
+                },
+                _ => 0,
+            })
+            .collect();
+        types.sort();
+        types
+    };
+
+    let narrowest = MsgTable::try_from(json).unwrap();
+    assert_eq!(types(&narrowest), [16, 16, 32, 64]);
+    let libubus = MsgTable::from_json(json, NumberEncoding::Int32OrInt64).unwrap();
+    assert_eq!(types(&libubus), [32, 32, 32, 64]);
+    let int64 = MsgTable::from_json(json, NumberEncoding::Int64).unwrap();
+    assert_eq!(types(&int64), [64, 64, 64, 64]);
+
+    /* widening a narrowest table gives the same as converting with the encoding */
+    let mut widened = narrowest.clone();
+    widened.widen_numbers(NumberEncoding::Int32OrInt64);
+    assert_eq!(types(&widened), types(&libubus));
+
+    /* explicit INT64 is never narrowed */
+    let mut explicit = MsgTable::from(vec![BlobMsg {
+        name: "id".into(),
+        data: BlobMsgPayload::Int64(1),
+    }]);
+    explicit.widen_numbers(NumberEncoding::Int32OrInt64);
+    assert!(matches!(explicit.0[0].data, BlobMsgPayload::Int64(1)));
+}
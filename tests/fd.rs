@@ -0,0 +1,138 @@
+#![cfg(feature = "tokio")]
+
+use std::io::{Read, Write};
+use tokio::net::UnixStream;
+use ubus::*;
+
+mod common;
+use common::{TEST_HELLO, message, reply};
+
+#[tokio::test]
+async fn test_invoke_with_fd() {
+    let (client, server) = UnixStream::pair().unwrap();
+
+    tokio::spawn(async move {
+        let (mut reader, mut writer) = split_unix_stream(server);
+        writer.put(TEST_HELLO).await.unwrap();
+
+        /* client's INVOKE comes with the write end of its pipe */
+        let invoke = UbusMsg::from_io(&mut reader).await.unwrap();
+        assert_eq!(invoke.header.cmd_type, UbusCmdType::INVOKE);
+        let mut client_pipe = std::fs::File::from(reader.take_fd().expect("fd with INVOKE"));
+        client_pipe.write_all(b"ping").unwrap();
+
+        /* reply with the read end of our pipe */
+        let (server_pipe_reader, mut server_pipe_writer) = std::io::pipe().unwrap();
+        server_pipe_writer.write_all(b"pong").unwrap();
+        drop(server_pipe_writer);
+        let status = reply(
+            &invoke,
+            UbusCmdType::STATUS,
+            vec![UbusBlob::Status(UbusMsgStatus::OK)],
+        );
+        writer
+            .put_with_fd(&status, Some(server_pipe_reader.into()))
+            .await
+            .unwrap();
+    });
+
+    let connection = Connection::new(split_unix_stream(client)).await.unwrap();
+
+    let (client_pipe_reader, client_pipe_writer) = std::io::pipe().unwrap();
+    let (reply_args, reply_fd) = connection
+        .invoke_with_fd(
+            0x13333337.into(),
+            "stream",
+            MsgTable::new(),
+            Some(client_pipe_writer.into()),
+        )
+        .await
+        .unwrap();
+    assert!(reply_args.0.is_empty());
+
+    let mut received = String::new();
+    std::fs::File::from(reply_fd.expect("fd with STATUS"))
+        .read_to_string(&mut received)
+        .unwrap();
+    assert_eq!(received, "pong");
+
+    let mut buf = [0u8; 4];
+    let mut client_pipe_reader = client_pipe_reader;
+    client_pipe_reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn test_reply_with_fd() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let (added_tx, added_rx) = tokio::sync::oneshot::channel();
+
+    let ubusd = tokio::spawn(async move {
+        let (mut reader, mut writer) = split_unix_stream(server);
+        writer.put(TEST_HELLO).await.unwrap();
+
+        let add_object = UbusMsg::from_io(&mut reader).await.unwrap();
+        assert_eq!(add_object.header.cmd_type, UbusCmdType::ADD_OBJECT);
+        writer
+            .put(&reply(
+                &add_object,
+                UbusCmdType::DATA,
+                vec![UbusBlob::ObjId(0x42.into()), UbusBlob::ObjType(0x43.into())],
+            ))
+            .await
+            .unwrap();
+        writer
+            .put(&reply(
+                &add_object,
+                UbusCmdType::STATUS,
+                vec![UbusBlob::Status(UbusMsgStatus::OK)],
+            ))
+            .await
+            .unwrap();
+        added_rx.await.unwrap();
+
+        /* a client calls our object */
+        let invoke = message(
+            UbusCmdType::INVOKE,
+            7,
+            0x1234,
+            vec![
+                UbusBlob::ObjId(0x42.into()),
+                UbusBlob::Method("stream".into()),
+                UbusBlob::Data(MsgTable::new()),
+            ],
+        );
+        writer.put(&invoke).await.unwrap();
+
+        let data = UbusMsg::from_io(&mut reader).await.unwrap();
+        assert_eq!(data.header.cmd_type, UbusCmdType::DATA);
+        let status = UbusMsg::from_io(&mut reader).await.unwrap();
+        assert_eq!(status.header.cmd_type, UbusCmdType::STATUS);
+        assert_eq!(u16::from(status.header.sequence), 7);
+        reader.take_fd().expect("fd with STATUS")
+    });
+
+    let connection = Connection::new(split_unix_stream(client)).await.unwrap();
+    let obj_id = connection
+        .add_server(
+            UbusServerObjectBuilder::new("test").method_with_request("stream", |request| {
+                assert!(request.fd.is_none());
+                let (pipe_reader, mut pipe_writer) = std::io::pipe().unwrap();
+                pipe_writer.write_all(b"pong").unwrap();
+                UbusReply {
+                    data: MsgTable::new(),
+                    fd: Some(pipe_reader.into()),
+                }
+            }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(obj_id, 0x42);
+    added_tx.send(()).unwrap();
+
+    let mut received = String::new();
+    std::fs::File::from(ubusd.await.unwrap())
+        .read_to_string(&mut received)
+        .unwrap();
+    assert_eq!(received, "pong");
+}
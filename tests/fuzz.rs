@@ -0,0 +1,165 @@
+use serde_json::json;
+use ubus::*;
+
+mod common;
+use common::message;
+
+/* tiny xorshift, deterministic so a failure can be reproduced by its seed */
+struct XorShift(u64);
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+fn sample_message() -> Vec<u8> {
+    let data: MsgTable = json!({
+        "name": "br-lan",
+        "up": true,
+        "mtu": 1500,
+        "speed": 2500000000i64,
+        "ratio": 0.5,
+        "statistics": {"rx_bytes": 1024, "ports": ["lan1", "lan2"]},
+    })
+    .try_into()
+    .unwrap();
+    message(
+        UbusCmdType::INVOKE,
+        1,
+        0x1234,
+        vec![
+            UbusBlob::ObjId(0x1234.into()),
+            UbusBlob::Method("status".into()),
+            UbusBlob::Status(UbusMsgStatus::OK),
+            UbusBlob::Active(true),
+            UbusBlob::Data(data),
+        ],
+    )
+}
+
+fn check_located(result: Result<(), UbusError>, len: usize) {
+    if let Err(UbusError::InvalidBlob { offset, .. }) = result {
+        assert!(offset <= len, "offset {} out of {} bytes", offset, len);
+    }
+}
+
+/* every decoder must return, never panic */
+fn decode_all(data: &[u8]) {
+    check_located(UbusBlob::try_from(data).map(drop), data.len());
+    check_located(BlobMsg::try_from(data).map(drop), data.len());
+    let _ = BlobIter::new(data).count();
+
+    let table = MsgTableRef::new(data);
+    for blobmsg in table.iter().map_while(Result::ok) {
+        let _ = format!("{:?}", blobmsg);
+    }
+    check_located(table.to_msg_table().map(drop), data.len());
+
+    let mut message = Vec::new();
+    message.extend_from_slice(
+        &UbusMsgHeader {
+            version: UbusMsgVersion::CURRENT,
+            cmd_type: UbusCmdType::DATA,
+            sequence: 1.into(),
+            peer: 0.into(),
+        }
+        .to_bytes(),
+    );
+    message.extend_from_slice(
+        &BlobTag::try_build(0, BlobTag::SIZE + data.len(), false)
+            .unwrap()
+            .to_bytes(),
+    );
+    message.extend_from_slice(data);
+    decode_message(&message);
+}
+
+fn decode_message(message: &[u8]) {
+    if let Ok(raw) = UbusMsgRef::from_bytes(message) {
+        let _ = raw.data().map(|data| data.to_msg_table());
+        check_located(UbusMsg::try_from(raw).map(drop), message.len());
+    }
+    #[cfg(feature = "tokio")]
+    decode_message_io(message);
+}
+
+#[cfg(feature = "tokio")]
+fn decode_message_io(message: &[u8]) {
+    let mut reader = std::io::Cursor::new(message.to_vec());
+    let mut buffer = Vec::new();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        if let Ok(raw) = UbusMsg::read_raw(&mut reader, &mut buffer).await {
+            let _ = raw.data().map(|data| data.to_msg_table());
+            let _ = raw.get_attr_status();
+            check_located(UbusMsg::try_from(raw).map(drop), message.len());
+        }
+    });
+}
+
+#[test]
+fn fuzz_random_bytes() {
+    let mut rng = XorShift(0x2545f4914f6cdd1d);
+    for _ in 0..5000 {
+        let len = rng.below(96);
+        let data = rng.bytes(len);
+        decode_all(&data);
+    }
+}
+
+#[test]
+fn fuzz_mutated_message() {
+    let message = sample_message();
+    let mut rng = XorShift(0x9e3779b97f4a7c15);
+    for _ in 0..5000 {
+        let mut mutated = message.clone();
+        for _ in 0..1 + rng.below(4) {
+            let idx = rng.below(mutated.len());
+            mutated[idx] = rng.next() as u8;
+        }
+        mutated.truncate(rng.below(message.len() + 1));
+        decode_message(&mutated);
+        decode_all(&mutated[(UbusMsgHeader::SIZE + BlobTag::SIZE).min(mutated.len())..]);
+    }
+}
+
+#[test]
+fn test_truncated_payloads_are_errors() {
+    /* INT32 blobmsg named "a" without payload, BOOL blobmsg without payload */
+    for blob_type in [BlobMsgType::INT32, BlobMsgType::BOOL, BlobMsgType::INT64] {
+        let tag = BlobTag::try_build(blob_type.value(), BlobTag::SIZE + 4, true).unwrap();
+        let mut data = tag.to_bytes().to_vec();
+        data.extend_from_slice(&[0x00, 0x01, b'a', 0x00]);
+        match BlobMsg::try_from(&data[..]) {
+            Err(UbusError::InvalidBlob {
+                offset,
+                blob_type: t,
+                ..
+            }) => {
+                assert_eq!(offset, 0);
+                assert_eq!(t, blob_type.value());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    /* STATUS attribute with only 2 bytes */
+    let tag = BlobTag::try_build(UbusBlobType::STATUS.value(), BlobTag::SIZE + 2, false).unwrap();
+    let mut data = tag.to_bytes().to_vec();
+    data.extend_from_slice(&[0, 0]);
+    assert!(matches!(
+        UbusBlob::try_from(&data[..]),
+        Err(UbusError::InvalidBlob { blob_type: 1, .. })
+    ));
+}
@@ -1,9 +1,14 @@
+#![cfg(feature = "tokio")]
+
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::UnixStream,
 };
 use ubus::*;
 
+mod common;
+use common::TEST_HELLO;
+
 #[tokio::test]
 async fn test_invoke_with_correct_raw_bytes() {
     let (client, mut server) = UnixStream::pair().unwrap();
@@ -19,6 +24,7 @@ async fn test_invoke_with_correct_raw_bytes() {
     });
 
     let mut connection = Connection::new(client.into_split()).await.unwrap();
+    assert_eq!(u32::from(connection.client_id()), 0x2eb863db);
 
     connection
         .invoke(0x13333337.into(), "info", r#"{}"#.try_into().unwrap())
@@ -26,9 +32,38 @@ async fn test_invoke_with_correct_raw_bytes() {
         .unwrap();
 }
 
-const TEST_HELLO: &[u8] = &[
-    0x00, 0x00, 0x00, 0x00, 0x2e, 0xb8, 0x63, 0xdb, 0x00, 0x00, 0x00, 0x04,
-];
+#[tokio::test]
+async fn test_invoke_raw() {
+    let (client, mut server) = UnixStream::pair().unwrap();
+
+    tokio::spawn(async move {
+        server.write_all(TEST_HELLO).await.unwrap();
+        let mut command = [0u8; TEST_TX.len()];
+        server.read_exact(&mut command).await.unwrap();
+        for i in TEST_RX {
+            server.write_all(i).await.unwrap();
+        }
+    });
+
+    let connection = Connection::new(client.into_split()).await.unwrap();
+    /* only `memory.total` is decoded, the reply is never parsed into a MsgTable */
+    let total = connection
+        .invoke_raw(
+            0x13333337.into(),
+            "info",
+            MsgTable::new(),
+            |data| match data.get("memory") {
+                Some(BlobMsgPayloadRef::Table(memory)) => match memory.get("total") {
+                    Some(BlobMsgPayloadRef::Int64(total)) => Some(total),
+                    _ => None,
+                },
+                _ => None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(total, Some(0x1e9a6000));
+}
 
 const TEST_TX: &[u8] = &[
     0x00, 0x05, 0x00, 0x01, 0x13, 0x33, 0x33, 0x37, 0x00, 0x00, 0x00, 0x1c, 0x03, 0x00, 0x00, 0x08,
@@ -0,0 +1,139 @@
+use ubus::*;
+
+fn blobmsg(name: &str, data: BlobMsgPayload) -> BlobMsg {
+    BlobMsg {
+        name: name.into(),
+        data,
+    }
+}
+
+/* JSON objects keep wire order only with serde_json/preserve_order, which needs std */
+#[cfg(feature = "std")]
+#[test]
+fn test_wire_order_kept() {
+    let json = r#"{"zone":"lan","mtu":1500,"devices":{"eth1":1,"eth0":0},"auto":true}"#;
+    let table = MsgTable::try_from(json).unwrap();
+    assert_eq!(
+        table.keys().collect::<Vec<_>>(),
+        ["zone", "mtu", "devices", "auto"]
+    );
+
+    /* through bytes and back, like a reply from a C daemon */
+    let bytes = Vec::<u8>::try_from(&table).unwrap();
+    let parsed = MsgTableRef::new(&bytes).to_msg_table().unwrap();
+    assert_eq!(parsed.to_string().unwrap(), json);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_duplicate_names() {
+    let table = MsgTable(vec![
+        blobmsg("dns", BlobMsgPayload::String("1.1.1.1".into())),
+        blobmsg("up", BlobMsgPayload::Bool(true)),
+        blobmsg("dns", BlobMsgPayload::String("8.8.8.8".into())),
+        blobmsg("dns", BlobMsgPayload::String("9.9.9.9".into())),
+        blobmsg(
+            "nested",
+            BlobMsgPayload::Table(vec![
+                blobmsg("a", BlobMsgPayload::Array(vec![])),
+                blobmsg("a", BlobMsgPayload::Int32(1)),
+            ]),
+        ),
+    ]);
+
+    let keep_last = table
+        .clone()
+        .to_json_object(DuplicateNames::KeepLast)
+        .unwrap();
+    assert_eq!(
+        serde_json::to_string(&keep_last).unwrap(),
+        r#"{"dns":"9.9.9.9","up":true,"nested":{"a":1}}"#
+    );
+
+    /* an array value is wrapped too, so it isn't mistaken for collected values */
+    let keep_all = table
+        .clone()
+        .to_json_object(DuplicateNames::KeepAll)
+        .unwrap();
+    assert_eq!(
+        serde_json::to_string(&keep_all).unwrap(),
+        r#"{"dns":["1.1.1.1","8.8.8.8","9.9.9.9"],"up":true,"nested":{"a":[[],1]}}"#
+    );
+
+    assert!(matches!(
+        table.to_json_object(DuplicateNames::Error),
+        Err(UbusError::DuplicateName(name)) if name == "dns"
+    ));
+}
+
+#[test]
+fn test_format_like_ubus_cli() {
+    let table = msgtable! {
+        "hostname": "OpenWrt",
+        "load": [0.5, -1.25],
+        "memory": { "total": 126976i32, "shared": 0i64 },
+        "empty": {},
+        "up": true,
+        "escape": "tab\there \"quoted\" \u{1}",
+        "nothing": null,
+    };
+    /* `ubus call` output, the C cli adds a newline at the end */
+    let expected = "{
+\t\"hostname\": \"OpenWrt\",
+\t\"load\": [
+\t\t0.500000,
+\t\t-1.250000
+\t],
+\t\"memory\": {
+\t\t\"total\": 126976,
+\t\t\"shared\": 0
+\t},
+\t\"empty\": {
+\t\t
+\t},
+\t\"up\": true,
+\t\"escape\": \"tab\\there \\\"quoted\\\" \\u0001\",
+\t\"nothing\": null
+}";
+    assert_eq!(table.to_string_ubus(), expected);
+
+    /* `ubus -S call` */
+    assert_eq!(
+        JsonFormatter::compact().format_table(&table),
+        r#"{"hostname":"OpenWrt","load":[0.500000,-1.250000],"memory":{"total":126976,"shared":0},"empty":{},"up":true,"escape":"tab\there \"quoted\" \u0001","nothing":null}"#
+    );
+}
+
+#[test]
+fn test_format_details() {
+    let formatter = JsonFormatter::compact();
+    /* type 7 is printed as bool, even when decoded as INT8 */
+    assert_eq!(formatter.format_payload(&BlobMsgPayload::Int8(5)), "true");
+    assert_eq!(
+        formatter.format_payload(&BlobMsgPayload::Double(f64::NAN)),
+        "nan"
+    );
+    assert_eq!(
+        formatter.format_payload(&BlobMsgPayload::Double(1e20)),
+        "100000000000000000000.000000"
+    );
+    /* C strings stop at nul */
+    assert_eq!(
+        formatter.format_payload(&BlobMsgPayload::String("a\0b".into())),
+        r#""a""#
+    );
+    assert_eq!(
+        formatter.format_blobmsg(&blobmsg("mtu", BlobMsgPayload::Int16(1500))),
+        r#""mtu":1500"#
+    );
+    assert_eq!(
+        JsonFormatter::indented(1).format_blobmsg(&blobmsg("list", BlobMsgPayload::Array(vec![]))),
+        "\"list\": [\n\t\t\n\t]"
+    );
+    assert_eq!(
+        formatter
+            .types(true)
+            .format_table(&msgtable! { "mtu": 1500i16, "name": "lan", "ips": [1u8] }),
+        r#"{"mtu":1500 /* int16 */,"name":"lan" /* string */,"ips":[true /* int8 */]}"#
+    );
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "tokio")]
+
+use serde_json::json;
+use std::io::Cursor;
+use ubus::*;
+
+mod common;
+use common::message;
+
+fn data_message(data: MsgTable) -> Vec<u8> {
+    message(UbusCmdType::DATA, 1, 0x1234, vec![UbusBlob::Data(data)])
+}
+
+/* a table nested `depth` times inside the Data table */
+fn nested_message(depth: usize) -> Vec<u8> {
+    let mut builder = BlobBuilder::new();
+    let nests: Vec<BlobNest> = (0..depth)
+        .map(|_| builder.open_table("t").unwrap())
+        .collect();
+    for nest in nests.into_iter().rev() {
+        builder.close(nest).unwrap();
+    }
+    /* as raw bytes, a `MsgTable` this deep is never built */
+    let data = UbusBlob::Unknown {
+        id: UbusBlobType::DATA.value(),
+        bytes: builder.as_slice().to_vec(),
+    };
+    message(UbusCmdType::DATA, 1, 0, vec![data])
+}
+
+#[tokio::test]
+async fn test_oversized_message_is_skipped() {
+    let large: MsgTable = json!({"blob": "x".repeat(8192)}).try_into().unwrap();
+    let small: MsgTable = json!({"up": true}).try_into().unwrap();
+
+    let mut stream = data_message(large);
+    stream.extend_from_slice(&data_message(small));
+    let mut reader = Cursor::new(stream);
+
+    let options = ParseOptions::default().max_message_size(4096);
+    assert!(matches!(
+        UbusMsg::from_io_with_options(&mut reader, options).await,
+        Err(UbusError::LimitExceeded { max: 4096, .. })
+    ));
+    /* the next message is still readable */
+    let next = UbusMsg::from_io_with_options(&mut reader, options)
+        .await
+        .unwrap();
+    assert!(matches!(next.ubus_blobs[..], [UbusBlob::Data(_)]));
+}
+
+fn depth(table: &[BlobMsg]) -> usize {
+    1 + table
+        .iter()
+        .map(|blobmsg| match &blobmsg.data {
+            BlobMsgPayload::Table(nested) | BlobMsgPayload::Array(nested) => depth(nested),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn data_depth(message: &UbusMsg) -> usize {
+    match &message.ubus_blobs[..] {
+        [UbusBlob::Data(data)] => depth(&data.0),
+        other => panic!("unexpected blobs {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_nesting_depth() {
+    let options = ParseOptions::default().max_depth(8);
+
+    /* Data table + 7 nested tables is exactly 8 levels */
+    let mut reader = Cursor::new(nested_message(7));
+    let message = UbusMsg::from_io_with_options(&mut reader, options)
+        .await
+        .unwrap();
+    assert_eq!(data_depth(&message), 8);
+
+    let mut reader = Cursor::new(nested_message(8));
+    assert!(matches!(
+        UbusMsg::from_io_with_options(&mut reader, options).await,
+        Err(UbusError::LimitExceeded { .. })
+    ));
+
+    /* lenient parsing drops the table beyond the limit, it's never parsed */
+    let mut reader = Cursor::new(nested_message(BLOBMSG_MAX_DEPTH + 10));
+    let message = UbusMsg::from_io_with_options(&mut reader, options.lenient(true))
+        .await
+        .unwrap();
+    assert_eq!(data_depth(&message), 8);
+
+    /* the borrowed view is lazy, but converting it to owned is limited as well */
+    let mut reader = Cursor::new(nested_message(BLOBMSG_MAX_DEPTH + 10));
+    let mut buffer = Vec::new();
+    let data = UbusMsg::read_raw(&mut reader, &mut buffer)
+        .await
+        .unwrap()
+        .data()
+        .unwrap();
+    assert!(data.iter().next().unwrap().is_ok());
+    assert!(matches!(
+        data.to_msg_table(),
+        Err(UbusError::LimitExceeded { .. })
+    ));
+    assert!(
+        data.to_msg_table_with_options(ParseOptions::default().max_depth(100))
+            .is_ok()
+    );
+}
@@ -1,3 +1,5 @@
+#![cfg(feature = "tokio")]
+
 use std::{println, time::Duration};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -0,0 +1,103 @@
+#![cfg(feature = "tokio")]
+
+use serde_json::json;
+use std::io::Cursor;
+use ubus::*;
+
+mod common;
+use common::message;
+
+/* Data table {"a": 1, "b": <broken>, "c": 3}, "b" claims an INT32 but carries only 1 byte */
+fn message_with_broken_middle() -> (Vec<u8>, usize) {
+    let blobmsg = |name: &str, value| {
+        Vec::<u8>::try_from(BlobMsg {
+            name: name.into(),
+            data: BlobMsgPayload::Int32(value),
+        })
+        .unwrap()
+    };
+    let mut broken = blobmsg("b", 2);
+    /* cut the payload to 1 byte: tag + namelen + "b\0" + 1 */
+    let tag = BlobTag::try_build(BlobMsgType::INT32.value(), BlobTag::SIZE + 4 + 1, true).unwrap();
+    broken[..BlobTag::SIZE].copy_from_slice(&tag.to_bytes());
+    broken.truncate(tag.next_tag());
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&blobmsg("a", 1));
+    let broken_offset = BlobTag::SIZE + data.len();
+    data.extend_from_slice(&broken);
+    data.extend_from_slice(&blobmsg("c", 3));
+
+    /* the header and the tag of all attributes come before the Data blob */
+    let header_len = UbusMsgHeader::SIZE + BlobTag::SIZE;
+    let data = UbusBlob::Unknown {
+        id: UbusBlobType::DATA.value(),
+        bytes: data,
+    };
+    (
+        message(UbusCmdType::DATA, 1, 0, vec![data]),
+        header_len + broken_offset,
+    )
+}
+
+#[tokio::test]
+async fn test_strict_reports_malformed_blob() {
+    let (raw, broken_offset) = message_with_broken_middle();
+    let mut reader = Cursor::new(raw);
+    match UbusMsg::from_io(&mut reader).await {
+        Err(UbusError::InvalidBlob {
+            offset, blob_type, ..
+        }) => {
+            assert_eq!(offset, broken_offset);
+            assert_eq!(blob_type, BlobMsgType::INT32.value());
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_lenient_skips_malformed_blob() {
+    let (raw, _) = message_with_broken_middle();
+    let mut reader = Cursor::new(raw);
+    let message = UbusMsg::from_io_with_options(&mut reader, ParseOptions::default().lenient(true))
+        .await
+        .unwrap();
+    let [UbusBlob::Data(data)] = &message.ubus_blobs[..] else {
+        panic!("unexpected blobs {:?}", message.ubus_blobs);
+    };
+    /* only the broken one is lost */
+    assert_eq!(
+        data.to_string_clone().unwrap(),
+        json!({"a": 1, "c": 3}).to_string()
+    );
+}
+
+#[tokio::test]
+async fn test_unknown_attribute_roundtrip() {
+    let raw = message(
+        UbusCmdType::STATUS,
+        1,
+        0x1234,
+        vec![
+            /* e.g. an attribute of a future ubusd, between known ones */
+            UbusBlob::Unknown {
+                id: 0x20,
+                bytes: vec![1, 2, 3, 4, 5],
+            },
+            UbusBlob::Status(UbusMsgStatus::OK),
+        ],
+    );
+
+    let parsed = UbusMsg::from_io(&mut Cursor::new(raw.clone()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        &parsed.ubus_blobs[..],
+        [
+            UbusBlob::Unknown { id: 0x20, bytes },
+            UbusBlob::Status(UbusMsgStatus::OK)
+        ] if bytes == &[1, 2, 3, 4, 5]
+    ));
+    assert_eq!(parsed.get_attr_status(), Some(UbusMsgStatus::OK));
+    assert_eq!(parsed.to_bytes(), raw);
+}
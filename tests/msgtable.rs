@@ -0,0 +1,158 @@
+use serde_json::json;
+use ubus::*;
+
+fn network_status() -> MsgTable {
+    json!({
+        "up": true,
+        "l3_device": "br-lan",
+        "uptime": 300,
+        "ipv4-address": [
+            {"address": "192.168.1.1", "mask": 24},
+            {"address": "10.0.0.1", "mask": 8},
+        ],
+        "route": [[1, 2], [3]],
+        "data": {"mtu": 1500},
+    })
+    .try_into()
+    .unwrap()
+}
+
+#[test]
+fn test_typed_getters() {
+    let table = network_status();
+    assert_eq!(table.len(), 6);
+    assert_eq!(table.get_str("l3_device"), Some("br-lan"));
+    assert_eq!(table.get_bool("up"), Some(true));
+    /* 300 is INT16 on wire, still an i64 here */
+    assert!(matches!(
+        table.get("uptime"),
+        Some(BlobMsgPayload::Int16(300))
+    ));
+    assert_eq!(table.get_i64("uptime"), Some(300));
+    assert_eq!(table.get_table("data").unwrap().get_i64("mtu"), Some(1500));
+    assert_eq!(table.get_array("ipv4-address").unwrap().len(), 2);
+
+    /* wrong type or missing */
+    assert_eq!(table.get_str("up"), None);
+    assert_eq!(table.get_i64("missing"), None);
+    assert!(table.get_table("ipv4-address").is_none());
+}
+
+#[test]
+fn test_query_path() {
+    let mut table = network_status();
+    assert_eq!(
+        table.query_str("ipv4-address[0].address"),
+        Some("192.168.1.1")
+    );
+    assert_eq!(table.query_i64("ipv4-address[1].mask"), Some(8));
+    assert_eq!(table.query_i64("route[0][1]"), Some(2));
+    assert_eq!(table.query_i64("data.mtu"), Some(1500));
+
+    assert!(table.query("ipv4-address[2].address").is_none());
+    assert!(table.query("up.nothing").is_none());
+    assert!(table.query("ipv4-address[x]").is_none());
+    assert!(table.query("ipv4-address[0").is_none());
+    assert!(table.query("").is_none());
+
+    *table.query_mut("ipv4-address[1].mask").unwrap() = BlobMsgPayload::Int32(16);
+    assert_eq!(table.query_i64("ipv4-address[1].mask"), Some(16));
+}
+
+#[test]
+fn test_mutation_keeps_order() {
+    let mut table = network_status();
+    let mut keys: Vec<String> = table.keys().map(String::from).collect();
+    assert!(
+        table
+            .insert("uptime", BlobMsgPayload::Int64(301))
+            .is_some_and(|old| old.as_i64() == Some(300))
+    );
+    assert!(
+        table
+            .insert("proto", BlobMsgPayload::String("static".into()))
+            .is_none()
+    );
+    assert!(matches!(
+        table.remove("route"),
+        Some(BlobMsgPayload::Array(_))
+    ));
+    assert!(table.remove("route").is_none());
+    /* replaced in place, new ones appended */
+    keys.retain(|key| key != "route");
+    keys.push("proto".into());
+    assert_eq!(table.keys().collect::<Vec<_>>(), keys);
+
+    /* duplicated names are legal in blobmsg, the first one wins */
+    table.extend([BlobMsg {
+        name: "up".into(),
+        data: BlobMsgPayload::Bool(false),
+    }]);
+    assert_eq!(table.get_bool("up"), Some(true));
+    assert_eq!(table.iter().filter(|(name, _)| *name == "up").count(), 2);
+
+    *table.entry("restarts").or_insert(BlobMsgPayload::Int32(0)) = BlobMsgPayload::Int32(1);
+    table
+        .entry("restarts")
+        .and_modify(|count| *count = BlobMsgPayload::Int32(count.as_i64().unwrap() as i32 + 1))
+        .or_insert(BlobMsgPayload::Int32(0));
+    assert!(matches!(
+        table.get("restarts"),
+        Some(BlobMsgPayload::Int32(2))
+    ));
+
+    for (_, payload) in table.iter_mut() {
+        if let BlobMsgPayload::Int64(v) = payload {
+            *v += 1;
+        }
+    }
+    assert_eq!(table.get_i64("uptime"), Some(302));
+}
+
+#[test]
+fn test_msgtable_macro() {
+    let ifname = String::from("br-lan");
+    let vlan: Option<u16> = None;
+    let table = msgtable! {
+        "name": "lan",
+        "ifname": ifname,
+        "mtu": 1500,
+        "metric": -10i16,
+        "rx_bytes": 5000000000u64,
+        "ratio": 0.5,
+        "up": true,
+        "vlan": vlan,
+        "ports": ["lan1", 2 + 2, [], { "nested": null }],
+        "dns": { "servers": [], "search": null, },
+        "empty": {},
+    };
+
+    assert!(matches!(
+        table.get("mtu"),
+        Some(BlobMsgPayload::Int32(1500))
+    ));
+    assert!(matches!(
+        table.get("metric"),
+        Some(BlobMsgPayload::Int16(-10))
+    ));
+    assert_eq!(table.get_u64("rx_bytes"), Some(5000000000));
+    assert_eq!(table.get_str("ifname"), Some("br-lan"));
+    assert!(matches!(table.get("vlan"), Some(BlobMsgPayload::Null)));
+    assert!(matches!(
+        table.query("ports[1]"),
+        Some(BlobMsgPayload::Int32(4))
+    ));
+    assert!(matches!(
+        table.query("ports[3].nested"),
+        Some(BlobMsgPayload::Null)
+    ));
+    assert!(table.get_table("empty").unwrap().is_empty());
+
+    /* same as JSON except the widths we picked */
+    #[cfg(feature = "std")]
+    assert_eq!(
+        table.to_string_clone().unwrap(),
+        r#"{"name":"lan","ifname":"br-lan","mtu":1500,"metric":-10,"rx_bytes":5000000000,"ratio":0.5,"up":true,"vlan":null,"ports":["lan1",4,[],{"nested":null}],"dns":{"servers":[],"search":null},"empty":{}}"#
+    );
+    assert!(msgtable! {}.is_empty());
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "tokio")]
+
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+};
+use ubus::*;
+
+mod common;
+use common::*;
+
+#[tokio::test]
+async fn test_ping() {
+    let (client, mut server) = UnixStream::pair().unwrap();
+
+    tokio::spawn(async move {
+        server.write_all(TEST_HELLO).await.unwrap();
+        let mut command = [0u8; PING_TX.len()];
+        server.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command[..], PING_TX);
+        for i in PING_RX {
+            server.write_all(i).await.unwrap();
+        }
+        /* keep the socket open */
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    });
+
+    let connection = Connection::new(client.into_split()).await.unwrap();
+
+    let rtt = connection.ping().await.unwrap();
+    assert!(rtt < Duration::from_secs(3));
+    assert_eq!(connection.state(), ConnectionState::Alive);
+}
+
+#[tokio::test]
+async fn test_keepalive_marks_failed() {
+    let (client, mut server) = UnixStream::pair().unwrap();
+
+    tokio::spawn(async move {
+        server.write_all(TEST_HELLO).await.unwrap();
+        /* swallow PINGs and never answer */
+        let mut buf = [0u8; 64];
+        while server.read(&mut buf).await.unwrap_or(0) > 0 {}
+    });
+
+    let mut connection = Connection::new(client.into_split()).await.unwrap();
+    connection.spawn_keepalive(Duration::from_millis(10), 1);
+
+    /* a PING gives up after the interval, not after the 3s of other requests */
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(connection.state(), ConnectionState::Failed);
+    assert!(matches!(
+        connection.ping().await,
+        Err(UbusError::ConnectionFailed())
+    ));
+}
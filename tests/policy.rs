@@ -0,0 +1,67 @@
+use ubus::*;
+
+static IFACE_POLICY: [BlobMsgPolicy; 4] = [
+    BlobMsgPolicy::new("interface", BlobMsgType::STRING),
+    BlobMsgPolicy::new("up", BlobMsgType::BOOL),
+    BlobMsgPolicy::new("uptime", BlobMsgType::INT32),
+    BlobMsgPolicy::new("data", BlobMsgType::UNSPEC),
+];
+
+fn event() -> MsgTable {
+    msgtable! {
+        "action": "ifup",
+        "interface": "wan",
+        "uptime": 42,
+        "data": { "proto": "dhcp" },
+        "interface": "lan",
+    }
+}
+
+#[test]
+fn test_parse_policy() {
+    let event = event();
+    let [interface, up, uptime, data] = event.parse_policy(&IFACE_POLICY).unwrap();
+    /* first one wins, like blobmsg_parse() */
+    assert_eq!(interface.unwrap().data.as_str(), Some("wan"));
+    assert!(up.is_none());
+    assert_eq!(uptime.unwrap().data.as_i64(), Some(42));
+    assert_eq!(
+        data.unwrap().data.as_table().unwrap().get_str("proto"),
+        Some("dhcp")
+    );
+
+    /* the same with the borrowed view */
+    let bytes = Vec::<u8>::try_from(&event).unwrap();
+    let [interface, up, uptime, data] = MsgTableRef::new(&bytes)
+        .parse_policy(&IFACE_POLICY)
+        .unwrap();
+    assert!(matches!(
+        interface.unwrap().data,
+        BlobMsgPayloadRef::String("wan")
+    ));
+    assert!(up.is_none());
+    assert!(matches!(uptime.unwrap().data, BlobMsgPayloadRef::Int32(42)));
+    assert!(matches!(data.unwrap().data, BlobMsgPayloadRef::Table(_)));
+}
+
+#[test]
+fn test_policy_mismatch() {
+    let event = msgtable! { "interface": "wan", "uptime": 42i64 };
+    let error = event.parse_policy(&IFACE_POLICY).unwrap_err();
+    assert!(matches!(
+        &error,
+        UbusError::PolicyMismatch { name, expected: BlobMsgType::INT32, found: BlobMsgType::INT64 } if name == "uptime"
+    ));
+    assert_eq!(error.to_string(), "uptime should be INT32, got INT64");
+
+    let bytes = Vec::<u8>::try_from(&event).unwrap();
+    assert!(matches!(
+        MsgTableRef::new(&bytes).parse_policy(&IFACE_POLICY),
+        Err(UbusError::PolicyMismatch { .. })
+    ));
+    /* broken bytes are reported, not treated as missing */
+    assert!(matches!(
+        MsgTableRef::new(&bytes[..bytes.len() - 4]).parse_policy(&IFACE_POLICY),
+        Err(UbusError::InvalidBlob { .. })
+    ));
+}
@@ -0,0 +1,107 @@
+use ubus::*;
+
+mod common;
+use common::message;
+
+fn decode(bytes: &[u8]) -> UbusMsg {
+    UbusMsg::try_from(UbusMsgRef::from_bytes(bytes).unwrap()).unwrap()
+}
+
+#[test]
+fn test_protocol_invoke_and_reply() {
+    let mut protocol = Protocol::new();
+    let invoke = message(
+        UbusCmdType::INVOKE,
+        7,
+        0x1234,
+        vec![
+            UbusBlob::ObjId(0xabcd.into()),
+            UbusBlob::Method("hello".into()),
+            UbusBlob::Data(msgtable! { "msg": "hi" }),
+            UbusBlob::User("root".into()),
+        ],
+    );
+    /* one byte at a time */
+    for byte in &invoke {
+        protocol.receive(&[*byte]).unwrap();
+    }
+    let Some(ProtocolEvent::Invoke(invoke)) = protocol.poll_event() else {
+        panic!("no invoke");
+    };
+    assert!(protocol.poll_event().is_none());
+    assert_eq!(invoke.method, "hello");
+    assert_eq!(invoke.args.get_str("msg"), Some("hi"));
+    assert_eq!(invoke.user.as_deref(), Some("root"));
+
+    protocol
+        .reply_data(&invoke, msgtable! { "echo": "hi" })
+        .unwrap();
+    protocol.reply_status(&invoke, UbusMsgStatus::OK).unwrap();
+    let data = decode(&protocol.poll_transmit().unwrap());
+    let status = decode(&protocol.poll_transmit().unwrap());
+    assert!(protocol.poll_transmit().is_none());
+    assert_eq!(data.header.cmd_type, UbusCmdType::DATA);
+    assert_eq!(u16::from(data.header.sequence), 7);
+    assert_eq!(u32::from(data.header.peer), 0x1234);
+    assert_eq!(data.get_attr_obj_id(), Some(0xabcd));
+    assert_eq!(status.get_attr_status(), Some(UbusMsgStatus::OK));
+}
+
+#[test]
+fn test_protocol_reply_status() {
+    let mut protocol = Protocol::new();
+    let sequence = protocol.invoke(1.into(), "nope", MsgTable::new()).unwrap();
+    assert!(protocol.is_pending(sequence));
+    protocol
+        .receive(&message(
+            UbusCmdType::STATUS,
+            sequence,
+            0x1234,
+            vec![
+                UbusBlob::ObjId(1.into()),
+                UbusBlob::Status(UbusMsgStatus::METHOD_NOT_FOUND),
+            ],
+        ))
+        .unwrap();
+    assert!(matches!(
+        protocol.poll_event(),
+        Some(ProtocolEvent::Reply {
+            result: Err(UbusError::Status(UbusMsgStatus::METHOD_NOT_FOUND)),
+            ..
+        })
+    ));
+    assert!(!protocol.is_pending(sequence));
+
+    /* a late reply of a cancelled request is dropped */
+    let sequence = protocol.ping().unwrap();
+    assert!(protocol.cancel(sequence));
+    protocol
+        .receive(&message(UbusCmdType::STATUS, sequence, 0x1234, vec![]))
+        .unwrap();
+    assert!(protocol.poll_event().is_none());
+}
+
+#[test]
+fn test_protocol_limits() {
+    let mut protocol = Protocol::with_options(ParseOptions::default().max_message_size(64));
+    let sequence = protocol.lookup("").unwrap();
+    let large = message(
+        UbusCmdType::DATA,
+        sequence,
+        0x1234,
+        vec![UbusBlob::ObjPath("x".repeat(100))],
+    );
+    let status = message(UbusCmdType::STATUS, sequence, 0x1234, vec![]);
+    /* the oversized DATA is thrown away as it comes, the STATUS after it is still found */
+    protocol.receive(&large[..10]).unwrap();
+    protocol.receive(&large[10..]).unwrap();
+    protocol.receive(&status).unwrap();
+    assert!(matches!(
+        protocol.poll_event(),
+        Some(ProtocolEvent::Reply { result: Ok(data), .. }) if data.is_empty()
+    ));
+
+    let mut broken = status.clone();
+    broken[0] = 1;
+    assert!(protocol.receive(&broken).is_err());
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "tokio")]
+
+use std::path::Path;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use ubus::*;
+
+mod common;
+use common::*;
+
+/* a minimal ubusd: say HELLO, then answer one PING */
+async fn fake_ubusd<S: AsyncReadExt + AsyncWriteExt + Unpin>(mut server: S) {
+    server.write_all(TEST_HELLO).await.unwrap();
+    let mut command = [0u8; PING_TX.len()];
+    server.read_exact(&mut command).await.unwrap();
+    assert_eq!(&command[..], PING_TX);
+    for i in PING_RX {
+        server.write_all(i).await.unwrap();
+    }
+    /* hold the stream until client is done */
+    let mut buf = [0u8; 64];
+    while server.read(&mut buf).await.unwrap_or(0) > 0 {}
+}
+
+#[tokio::test]
+async fn test_duplex() {
+    let (client, server) = tokio::io::duplex(4096);
+    tokio::spawn(fake_ubusd(server));
+
+    let connection = Connection::from_stream(client).await.unwrap();
+    assert_eq!(u32::from(connection.client_id()), 0x2eb863db);
+    connection.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (server, _) = listener.accept().await.unwrap();
+        fake_ubusd(server).await;
+    });
+
+    let connection = Connection::connect_tcp(addr).await.unwrap();
+    connection.ping().await.unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_abstract_socket() {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixListener};
+
+    let name = format!("ubus-rs-test-{}", std::process::id());
+    let listener =
+        UnixListener::bind_addr(&SocketAddr::from_abstract_name(name.as_bytes()).unwrap()).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+    tokio::spawn(async move {
+        let (server, _) = listener.accept().await.unwrap();
+        fake_ubusd(server).await;
+    });
+
+    let connection = Connection::connect(Path::new(&format!("@{}", name)))
+        .await
+        .unwrap();
+    connection.ping().await.unwrap();
+}
@@ -0,0 +1,83 @@
+use ubus::*;
+
+/* an SSID in latin-1, as hostapd may report it */
+fn ssid_table() -> Vec<u8> {
+    let table = msgtable! {
+        "ssid": b"caf\xe9".as_slice(),
+        "channel": 6,
+    };
+    Vec::<u8>::try_from(&table).unwrap()
+}
+
+#[test]
+fn test_utf8_policy() {
+    let bytes = ssid_table();
+    let parse = |utf8| {
+        let options = ParseOptions::default().utf8(utf8);
+        MsgTableRef::with_options(&bytes, options).to_msg_table_with_options(options)
+    };
+
+    assert!(matches!(
+        MsgTableRef::new(&bytes).to_msg_table(),
+        Err(UbusError::InvalidBlob { offset: 0, .. })
+    ));
+
+    let lossy = parse(Utf8Policy::Lossy).unwrap();
+    assert_eq!(lossy.get_str("ssid"), Some("caf\u{fffd}"));
+    assert_eq!(lossy.get_i64("channel"), Some(6));
+
+    /* lossless, encoding it again gives the same bytes */
+    let raw = parse(Utf8Policy::Bytes).unwrap();
+    assert!(matches!(raw.get("ssid"), Some(BlobMsgPayload::Bytes(ssid)) if ssid == b"caf\xe9"));
+    assert_eq!(Vec::<u8>::try_from(&raw).unwrap(), bytes);
+
+    let options = ParseOptions::default().utf8(Utf8Policy::Bytes);
+    assert!(matches!(
+        MsgTableRef::with_options(&bytes, options).get("ssid"),
+        Some(BlobMsgPayloadRef::Bytes(b"caf\xe9"))
+    ));
+    let blobmsg = BlobMsg::from_bytes_with_options(&bytes, options).unwrap();
+    assert_eq!(blobmsg.data.as_bytes(), Some(&b"caf\xe9"[..]));
+}
+
+#[test]
+fn test_utf8_policy_borrowed_to_owned() {
+    let bytes = ssid_table();
+    for utf8 in [Utf8Policy::Lossy, Utf8Policy::Bytes] {
+        let options = ParseOptions::default().utf8(utf8);
+        let owned = BlobMsg::from_bytes_with_options(&bytes, options).unwrap();
+        let view = MsgTableRef::with_options(&bytes, options)
+            .iter()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Vec::<u8>::try_from(BlobMsg::try_from(view).unwrap()).unwrap(),
+            Vec::<u8>::try_from(owned).unwrap()
+        );
+    }
+    let options = ParseOptions::default().utf8(Utf8Policy::Lossy);
+    let view = MsgTableRef::with_options(&bytes, options).iter().next();
+    let lossy = view.unwrap().unwrap().to_blob_msg().unwrap();
+    assert_eq!(lossy.data.as_str(), Some("caf\u{fffd}"));
+}
+
+#[test]
+fn test_bytes_to_json() {
+    let table = msgtable! {
+        "ssid": b"caf\xe9".as_slice(),
+        "one": vec![0xffu8],
+        "two": vec![0xffu8, 0xfe],
+        "empty": Vec::<u8>::new(),
+    };
+    #[cfg(feature = "std")]
+    assert_eq!(
+        table.clone().to_string().unwrap(),
+        r#"{"ssid":"Y2Fm6Q==","one":"/w==","two":"//4=","empty":""}"#
+    );
+
+    let lossy = table
+        .to_json_object_with(JsonOptions::default().bytes(BytesEncoding::Lossy))
+        .unwrap();
+    assert_eq!(lossy["ssid"], "caf\u{fffd}");
+}